@@ -4,6 +4,8 @@ use sdl2::{
     mouse::MouseButton,
 };
 use std::time::Instant;
+use std::collections::BTreeMap;
+use std::path::Path;
 
 use layout::{EditorLayout, edit_num};
 use prev::Prev;
@@ -18,13 +20,22 @@ use tool::{
     PreviewContext,
     Editor,
     Receiver,
+    Property, Properties,
 };
 
 use render::{self, Canvas};
-use draw::{Shape, Bounded};
+use draw::{Shape, Bounded, Palette};
+use draw::color::{Space, Distance};
+use format::{Source, Error};
+use format::sheet::{Slice, Order, Layout};
+use format::palette::{Import, SortBy, Convert};
+use format::preview::{self, Background, Lines};
+use format::tiled::Tileset;
 
 use theme::*;
 use grid::Grid;
+use recent::Recent;
+use autosave::{Autosave, Snapshot};
 
 
 #[macro_export]
@@ -83,6 +94,55 @@ pub struct App {
 
     pub editor: Editor,
     pub grid: Grid,
+    pub slice: Slice,
+
+    /// Scaled export: factor, grid overlay and what fills the transparent index.
+    pub export_scale: i16,
+    pub export_grid: bool,
+    pub export_fill: bool,
+    pub export_checker: bool,
+    /// GIF plays, 0 loops forever.
+    pub gif_plays: i16,
+
+    /// Sheet export: current layer or composite, layout, grid columns (0 is square),
+    /// padding and extruded edge pixels.
+    pub sheet_layer: bool,
+    pub sheet_layout: Layout,
+    pub sheet_columns: i16,
+    pub sheet_padding: i16,
+    pub sheet_extrude: i16,
+
+    /// Palette extraction: colour count, 0 keeps every unique colour.
+    pub extract_count: i16,
+    pub extract_kmeans: bool,
+    /// Sorting of extracted and compacted palettes.
+    pub sort_hue: bool,
+    pub sort_luminance: bool,
+
+    /// Grid cell whose properties the panel edits.
+    pub tile: i16,
+
+    /// Palette entry and text of the hex field while it is being typed in.
+    pub hex: Option<(u8, String)>,
+    /// Ramp from the selected entry: other end, colours spread between the
+    /// ends (0 fills every entry), blending space and hue shift in degrees.
+    pub ramp_end: i16,
+    pub ramp_count: i16,
+    pub ramp_space: Space,
+    pub ramp_shift: i16,
+
+    /// Palette a conversion would move the sprite to and the entry each
+    /// old one lands on, shown on the canvas until applied or cancelled.
+    pub convert: Option<(Palette<u32>, [u8; 256])>,
+    pub convert_opts: Convert,
+    /// Override being entered: sprite entry and new palette entry.
+    pub override_from: i16,
+    pub override_to: i16,
+
+    /// Palette entry being dragged to a new index.
+    pub pal_drag: Option<u8>,
+    /// Last colour set through the HSV sliders, keeps the hue of greys.
+    pub pal_hsv: (u32, (f32, f32, f32)),
 
     pub current: CurrentTool,
 
@@ -93,6 +153,15 @@ pub struct App {
 
     pub mouse: Point2<i32>,
     pub drag: bool,
+    /// Modifiers as of the last key event, refreshed from SDL before a file drop.
+    pub keymod: keyboard::Mod,
+
+    pub filename: Option<String>,
+    pub error: Option<Error>,
+    pub recent: Recent,
+    pub autosave: Autosave,
+    /// Snapshots from earlier sessions waiting for restore or discard.
+    pub recovery: Vec<Snapshot>,
 
     file_menu_id: ui::Id,
     brush_menu_id: ui::Id,
@@ -112,6 +181,9 @@ impl App {
         editor.sync();
         editor.image.as_mut_receiver().pos = Point2::new(300, 200);
 
+        let autosave = Autosave::new();
+        let recovery = autosave.recover();
+
         Self {
             init: false,
 
@@ -129,6 +201,33 @@ impl App {
                 size: Vector2::new(16, 16),
                 offset: Vector2::new(0, 0),
             },
+            slice: Slice::default(),
+            export_scale: 4,
+            export_grid: false,
+            export_fill: false,
+            export_checker: false,
+            gif_plays: 0,
+            sheet_layer: false,
+            sheet_layout: Layout::Grid(0),
+            sheet_columns: 0,
+            sheet_padding: 0,
+            sheet_extrude: 0,
+            extract_count: 0,
+            extract_kmeans: false,
+            sort_hue: false,
+            sort_luminance: false,
+            tile: 0,
+            hex: None,
+            convert: None,
+            convert_opts: Convert { distance: Distance::Oklab, overrides: BTreeMap::new() },
+            override_from: 0,
+            override_to: 0,
+            ramp_end: 15,
+            ramp_count: 0,
+            ramp_space: Space::Oklab,
+            ramp_shift: 0,
+            pal_drag: None,
+            pal_hsv: (0, (0.0, 0.0, 0.0)),
             editor,
             current: CurrentTool::Freehand,
             prim: Primitive::new(),
@@ -141,6 +240,13 @@ impl App {
 
             mouse: Point2::new(-100, -100),
             drag: false,
+            keymod: keyboard::NOMOD,
+
+            filename: None,
+            error: None,
+            recent: Recent::load(),
+            autosave,
+            recovery,
 
             time: Instant::now(),
             data: Vec::new(),
@@ -225,7 +331,356 @@ impl App {
             .zoom(y, |diff| v * diff);
     }
 
+    /// Keeps the error around until the user dismisses it.
+    pub fn report(&mut self, err: Error) {
+        println!("error: {}", err);
+        self.error = Some(err);
+    }
+
+    /// Replaces the document, returns `false` if the file can't be loaded.
+    pub fn open(&mut self, name: String) -> bool {
+        println!("open_file: {}", name);
+        match ::open::load_sprite(&name) {
+            Ok(image) => {
+                self.editor.recreate(image);
+                self.autosave.reset();
+                self.recent.add(&name);
+                self.filename = Some(name);
+                true
+            }
+            Err(err) => {
+                self.report(err);
+                false
+            }
+        }
+    }
+
+    pub fn save(&mut self, ask: bool) {
+        let filename = match self.filename {
+            Some(ref name) if !ask && ::open::is_native(name) => Some(name.clone()),
+            _ => ::open::save_file(),
+        };
+
+        if let Some(name) = filename {
+            println!("save_file: {}", name);
+            let result = ::open::save_sprite(&name, self.editor.image.as_receiver());
+            match result {
+                Ok(()) => {
+                    let changes = self.editor.changes();
+                    self.autosave.saved(self.filename.as_ref().map(|s| s.as_str()), changes);
+                    self.autosave.saved(Some(name.as_str()), changes);
+                    self.recent.add(&name);
+                    self.filename = Some(name);
+                }
+                Err(err) => self.report(err.into()),
+            }
+        }
+    }
+
+    pub fn export(&mut self, source: Source) {
+        if let Some(name) = ::open::export_file() {
+            println!("export_file: {}", name);
+            let repeat = ::format::gif::Loop::plays(self.gif_plays as u16);
+            let result = ::open::export_sprite(&name, self.editor.image.as_receiver(), source, repeat);
+            if let Err(err) = result {
+                self.report(err.into());
+            }
+        }
+    }
+
+    pub fn import_sheet(&mut self) {
+        let name = match ::open::open_file() {
+            Some(name) => name,
+            None => return,
+        };
+        println!("import_sheet: {}", name);
+        let image = match ::open::load_sprite(&name) {
+            Ok(image) => image,
+            Err(err) => return self.report(err),
+        };
+
+        let mut slice = self.slice;
+        let (size, offset) = self.grid.cells();
+        slice.size = size;
+        slice.offset = offset;
+
+        self.editor.recreate(::format::sheet::slice(&image, &slice));
+        self.autosave.reset();
+        self.filename = None;
+    }
+
+    /// Brings back unsaved work, the snapshot stays on disk until the next save.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        println!("restore: {}", snapshot.path.display());
+        match ::open::load_sprite(&snapshot.path) {
+            Ok(image) => {
+                self.editor.recreate(image);
+                self.autosave.reset();
+                snapshot.remove();
+                self.filename = snapshot.original;
+            }
+            Err(err) => self.report(err),
+        }
+    }
+
+    pub fn import_palette(&mut self, mode: Import) {
+        if let Some(name) = ::open::open_palette_file() {
+            self.load_palette(&name, mode);
+        }
+    }
+
+    pub fn load_palette(&mut self, name: &str, mode: Import) {
+        println!("import_palette: {}", name);
+        match ::open::load_palette(name) {
+            Ok(palette) => self.editor.rewrite(|m| ::format::palette::apply(m, &palette, mode)),
+            Err(err) => self.report(err),
+        }
+    }
+
+    /// Puts the image on a new layer of the current sprite.
+    pub fn add_layer(&mut self, name: &str) {
+        println!("add_layer: {}", name);
+        match ::open::load_sprite(name) {
+            Ok(image) => {
+                let layer = Path::new(name).file_stem()
+                    .map_or("Layer".to_string(), |stem| stem.to_string_lossy().into_owned());
+                let layer = ::format::to_layer(self.editor.image.as_receiver(), &image, &layer);
+                self.editor.add_layer(layer);
+            }
+            Err(err) => self.report(err),
+        }
+    }
+
+    /// Palettes replace the current one, images are opened,
+    /// or added as a layer while Shift or Ctrl is held.
+    pub fn drop_file(&mut self, name: String) {
+        println!("drop_file: {}", name);
+        let add = self.keymod.intersects(
+            keyboard::LSHIFTMOD | keyboard::RSHIFTMOD | keyboard::LCTRLMOD | keyboard::RCTRLMOD);
+        if ::open::is_palette(&name) {
+            self.load_palette(&name, Import::KeepIndices);
+        } else if add {
+            self.add_layer(&name);
+        } else {
+            self.open(name);
+        }
+    }
+
+    fn palette_sort(&self) -> Option<SortBy> {
+        if self.sort_hue {
+            Some(SortBy::Hue)
+        } else if self.sort_luminance {
+            Some(SortBy::Luminance)
+        } else {
+            None
+        }
+    }
+
+    /// Replaces the palette with the colours of an image, keeping indices.
+    pub fn extract_palette(&mut self) {
+        use format::palette::{extract, sort, apply, Extract};
+        use format::quantize::Quantizer;
+
+        let name = match ::open::open_file() {
+            Some(name) => name,
+            None => return,
+        };
+        println!("extract_palette: {}", name);
+
+        let mode = if self.extract_count == 0 {
+            Extract::Unique
+        } else {
+            let q = if self.extract_kmeans { Quantizer::KMeans } else { Quantizer::MedianCut };
+            Extract::Reduce(self.extract_count as usize, q)
+        };
+        let result = ::open::load_colors(&name).and_then(|colors| extract(&colors, mode));
+        match result {
+            Ok(mut palette) => {
+                if let Some(by) = self.palette_sort() {
+                    sort(&mut palette.map[..palette.size], by);
+                }
+                self.editor.rewrite(|m| apply(m, &palette, Import::KeepIndices));
+            }
+            Err(err) => self.report(err),
+        }
+    }
+
+    /// Drops unused palette entries.
+    pub fn compact_palette(&mut self) {
+        let by = self.palette_sort();
+        let mut size = 0;
+        self.editor.rewrite(|m| size = ::format::palette::compact(m, by));
+        println!("compact_palette: {} colours", size);
+    }
+
+    /// Sorts the palette, pixels are remapped so the image looks the same.
+    pub fn sort_palette(&mut self, by: SortBy) {
+        println!("sort_palette: {:?}", by);
+        self.editor.rewrite(|m| ::format::palette::sort_entries(m, by));
+    }
+
+    /// Drops a dragged palette entry onto another one: moves it there,
+    /// or swaps the two while Shift is held.
+    pub fn drop_entry(&mut self, from: u8, to: u8) {
+        use format::palette::{move_entry, swap_entries};
+
+        let swap = self.keymod.intersects(keyboard::LSHIFTMOD | keyboard::RSHIFTMOD);
+        println!("drop_entry: {} -> {} swap: {}", from, to, swap);
+        self.editor.rewrite(|m| if swap {
+            swap_entries(m, from, to)
+        } else {
+            move_entry(m, from, to)
+        });
+        self.editor.color = to;
+    }
+
+    /// Starts converting the sprite to the palette of a file, see `convert_panel`.
+    pub fn convert_palette(&mut self) {
+        let name = match ::open::open_palette_source() {
+            Some(name) => name,
+            None => return,
+        };
+        println!("convert_palette: {}", name);
+        match ::open::load_palette_from(&name) {
+            Ok(palette) => {
+                self.convert = Some((palette, [0; 256]));
+                self.update_convert();
+            }
+            Err(err) => self.report(err),
+        }
+    }
+
+    fn update_convert(&mut self) {
+        if let Some((ref palette, ref mut table)) = self.convert {
+            let m = self.editor.image.as_receiver();
+            *table = ::format::palette::convert_table(m, palette, &self.convert_opts);
+        }
+    }
+
+    /// Rewrites every layer and frame onto the pending palette, one step in the history.
+    pub fn apply_convert(&mut self) {
+        use format::palette::convert;
+
+        if let Some((palette, _)) = self.convert.take() {
+            let opts = &self.convert_opts;
+            self.editor.rewrite(|m| convert(m, &palette, opts));
+        }
+    }
+
+    pub fn export_palette(&mut self) {
+        if let Some(name) = ::open::save_palette_file() {
+            println!("export_palette: {}", name);
+            let result = ::open::save_palette(&name, &self.editor.image.as_receiver().palette);
+            if let Err(err) = result {
+                self.report(err.into());
+            }
+        }
+    }
+
+    pub fn export_sheet(&mut self) {
+        if let Some(name) = ::open::export_png_file() {
+            println!("export_sheet: {}", name);
+            let layer = self.editor.image.as_receiver().layer;
+            let opts = ::format::sheet::Options {
+                source: if self.sheet_layer { Source::Layer(layer) } else { Source::Composite },
+                layout: match self.sheet_layout {
+                    Layout::Grid(_) => Layout::Grid(self.sheet_columns as usize),
+                    layout => layout,
+                },
+                padding: self.sheet_padding as usize,
+                extrude: self.sheet_extrude as usize,
+            };
+            let result = ::open::export_sheet(&name, self.editor.image.as_receiver(), &opts);
+            if let Err(err) = result {
+                self.report(err.into());
+            }
+        }
+    }
+
+    pub fn export_scaled(&mut self) {
+        if let Some(name) = ::open::export_png_file() {
+            println!("export_scaled: {}", name);
+            let opts = self.preview_options();
+            let result = ::open::export_preview(&name, self.editor.image.as_receiver(), &opts);
+            if let Err(err) = result {
+                self.report(err.into());
+            }
+        }
+    }
+
+    pub fn export_tileset(&mut self) {
+        let (size, offset) = self.grid.cells();
+        let tileset = match Tileset::from_grid(size, offset) {
+            Ok(tileset) => tileset,
+            Err(err) => return self.report(err.into()),
+        };
+        if let Some(name) = ::open::export_png_file() {
+            println!("export_tileset: {}", name);
+            let result = ::open::export_tileset(&name, self.editor.image.as_receiver(), &tileset);
+            if let Err(err) = result {
+                self.report(err.into());
+            }
+        }
+    }
+
+    fn preview_options(&self) -> preview::Options {
+        let scale = self.export_scale.max(1) as usize;
+        let background = if self.export_checker {
+            Background::Checker { a: CHECKER_LIGHT, b: CHECKER_DARK, size: 4 * scale }
+        } else if self.export_fill {
+            Background::Solid(self.editor.pal(self.editor.color) | 0xFF)
+        } else {
+            Background::Transparent
+        };
+        let grid = if self.export_grid {
+            let (size, offset) = self.grid.cells();
+            Some(Lines { size, offset, color: GRID_COLOR })
+        } else {
+            None
+        };
+        preview::Options { scale, grid, background, .. preview::Options::default() }
+    }
+
+    /// Typing into the hex field, true when the event was used.
+    fn hex_event(&mut self, event: &Event) -> bool {
+        let (index, text) = match self.hex.take() {
+            Some(hex) => hex,
+            None => return false,
+        };
+        let mut text = text;
+        match *event {
+            Event::TextInput { text: ref input, .. } => {
+                let digits = input.chars().filter(|c| c.is_digit(16));
+                text.extend(digits.take(6 - text.len().min(6)));
+            }
+            Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => { text.pop(); }
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return true,
+            Event::KeyDown { keycode: Some(Keycode::Return), .. } |
+            Event::KeyDown { keycode: Some(Keycode::KpEnter), .. } => {
+                // three digits are shorthand for doubled ones, like in CSS
+                if text.len() == 3 {
+                    text = text.chars().flat_map(|c| vec![c, c]).collect();
+                }
+                if let Ok(rgb) = u32::from_str_radix(&text, 16) {
+                    let alpha = self.editor.pal(index) & 0xFF;
+                    self.editor.set_pal(index, rgb << 8 | alpha);
+                }
+                return true;
+            }
+            // other keys keep working while the field is open
+            _ => {
+                self.hex = Some((index, text));
+                return false;
+            }
+        }
+        self.hex = Some((index, text));
+        true
+    }
+
     pub fn event(&mut self, event: Event) {
+        if self.hex_event(&event) {
+            return;
+        }
         match event {
         Event::MouseMotion {x, y, xrel, yrel, ..} => {
             let p = Point2::new(x as i32, y as i32);
@@ -236,7 +691,8 @@ impl App {
 
         Event::Quit {..} => self.quit = true,
 
-        Event::KeyUp { keycode: Some(keycode), .. } => {
+        Event::KeyUp { keycode: Some(keycode), keymod, .. } => {
+            self.keymod = keymod;
             match keycode {
                 Keycode::LShift |
                 Keycode::RShift => self.special(false),
@@ -247,6 +703,7 @@ impl App {
         }
 
         Event::KeyDown { keycode: Some(keycode), keymod, ..} => {
+            self.keymod = keymod;
             let _shift = keymod.intersects(keyboard::LSHIFTMOD | keyboard::RSHIFTMOD);
             let _alt = keymod.intersects(keyboard::LALTMOD | keyboard::RALTMOD);
             let _ctrl = keymod.intersects(keyboard::LCTRLMOD | keyboard::RCTRLMOD);
@@ -264,6 +721,15 @@ impl App {
                 Keycode::U => self.editor.undo(),
                 Keycode::R => self.editor.redo(),
 
+                Keycode::Left => {
+                    let frame = self.editor.frame();
+                    self.editor.set_frame(frame.saturating_sub(1));
+                }
+                Keycode::Right => {
+                    let frame = self.editor.frame();
+                    self.editor.set_frame(frame + 1);
+                }
+
                 //Keycode::Tab if shift => render.key = Some(gui::Key::PrevWidget),
                 //Keycode::Tab if !shift => render.key = Some(gui::Key::NextWidget),
 
@@ -283,12 +749,15 @@ impl App {
         Event::MouseButtonUp { mouse_btn: MouseButton::Left, x, y, .. } => {
             let p = Point2::new(x as i32, y as i32);
             self.mouse_release(p);
+            self.editor.commit_pal();
             self.ui_mouse.cursor = Point2::new(x as f32, y as f32);
             self.ui_mouse.released = true;
         }
 
         Event::MouseWheel { y, ..} => { self.zoom_from_mouse(y as i32); }
 
+        Event::DropFile { filename, .. } => self.drop_file(filename),
+
         _ => (),
         }
     }
@@ -300,6 +769,15 @@ impl App {
         let size = w * h * 4;
         self.data.resize(size as usize, 0u8);
 
+        // a pending conversion shows every entry as the one it would become
+        let converted = self.convert.as_ref().map(|&(ref palette, ref table)| {
+            let mut colors = [0u32; 256];
+            for (c, &i) in colors.iter_mut().zip(table.iter()) {
+                *c = palette[i];
+            }
+            colors
+        });
+
         let ptr = self.data.as_mut_ptr();
         self.editor.draw_pages(|page, palette| {
             let transparent = page.transparent;
@@ -307,7 +785,7 @@ impl App {
             for &c in &page.page {
                 unsafe {
                     if Some(c) != transparent {
-                        let c = palette[c].to_le();
+                        let c = converted.as_ref().map_or(palette[c], |colors| colors[c as usize]).to_le();
                         *ptr.add(0) = ( c        & 0xFF) as u8;
                         *ptr.add(1) = ((c >>  8) & 0xFF) as u8;
                         *ptr.add(2) = ((c >> 16) & 0xFF) as u8;
@@ -320,11 +798,14 @@ impl App {
 
         let ptr = self.data.as_mut_ptr();
         if self.in_widget {
-            self.preview(Prev {
-                ptr,
-                rect: Rect::from_coords_and_size(0, 0, w as i32, h as i32),
-                editor: &self.editor,
-            });
+            let rect = Rect::from_coords_and_size(0, 0, w as i32, h as i32);
+            self.preview(Prev { ptr, rect, editor: &self.editor });
+            match self.current {
+                CurrentTool::Freehand | CurrentTool::Primitive(_) => {
+                    Prev { ptr, rect, editor: &self.editor }.brush_outline(self.mouse);
+                }
+                _ => (),
+            }
         }
         t.update(None, &self.data, self.editor.size().x as usize * 4).unwrap();
     }
@@ -378,22 +859,24 @@ impl App {
                     let dim = Vector2::new(SIZE as f32, SIZE as f32);
                     let r = Rect::from_min_dim(min, dim);
                     if is {
-                        canvas.quad(0x333333_FFu32.to_be(), r);
+                        canvas.quad(CHECKER_LIGHT.to_be(), r);
                     } else {
-                        canvas.quad(0x000000_FFu32.to_be(), r);
+                        canvas.quad(CHECKER_DARK.to_be(), r);
                     }
                 }
             }
         }
 
         {
+            self.editor.update_contrast();
             self.paint_sprites(canvas);
             let rect = self.editor.rect();
             let pos = Point2::new(rect.min.x as i16, rect.min.y as i16);
             let zoom = self.editor.zoom() as i16;
             canvas.image_zoomed(EDITOR_SPRITE_ID, pos, zoom);
             {
-                self.grid.paint(canvas, zoom, rect);
+                let editor = &self.editor;
+                self.grid.paint(canvas, zoom, rect, |x, y| editor.overlay(x, y));
             }
         }
         canvas.unclip();
@@ -413,6 +896,12 @@ impl App {
             Flow::with_height(STATUSBAR_HEIGHT).expand_across() => |ctx| { self.statusbar(ctx) }
         });
         self.second_menubar(ctx);
+
+        self.autosave.update(
+            self.editor.image.as_receiver(),
+            self.filename.as_ref().map(|s| s.as_str()),
+            self.editor.changes(),
+        );
     }
 
     fn content(&mut self, ctx: ui::Context<Canvas>) {
@@ -467,6 +956,7 @@ impl App {
                 self.editor.resize_brush();
             }
 
+            self.palette_editor(&mut lay);
 
             lay.header_checkbox("Grid", &mut self.grid.visible);
             if self.grid.visible {
@@ -474,6 +964,67 @@ impl App {
                 lay.num("size", "y", &mut self.grid.size.y, 1, 0, None);
                 lay.num("offset", "x", &mut self.grid.offset.x, 1, None, None);
                 lay.num("offset", "y", &mut self.grid.offset.y, 1, None, None);
+
+                let mut by_column = self.slice.order == Order::ColumnMajor;
+                lay.toggle_prop("skip empty", &mut self.slice.skip_empty);
+                lay.toggle_prop("by column", &mut by_column);
+                self.slice.order = if by_column { Order::ColumnMajor } else { Order::RowMajor };
+
+                lay.num("tile", "#", &mut self.tile, 1, 0, None);
+                let id = self.tile as usize;
+                let props = self.editor.image.as_receiver().tiles.get(&id).cloned();
+                let mut collision = props.as_ref()
+                    .and_then(|props| props.get(COLLISION))
+                    .map_or(false, |value| *value == Property::Bool(true));
+                if lay.toggle_prop("collision", &mut collision) {
+                    let mut props = props.unwrap_or_else(Properties::new);
+                    if collision {
+                        props.insert(COLLISION.to_string(), Property::Bool(true));
+                    } else {
+                        props.remove(COLLISION);
+                    }
+                    let props = if props.is_empty() { None } else { Some(props) };
+                    self.editor.set_tile(id, props);
+                }
+            }
+
+            lay.header("Export scaled");
+            lay.num("scale", "x", &mut self.export_scale, 1, preview::MIN_SCALE as i16, preview::MAX_SCALE as i16);
+            lay.toggle_prop("grid", &mut self.export_grid);
+            lay.toggle_prop("fill bg", &mut self.export_fill);
+            lay.toggle_prop("checker bg", &mut self.export_checker);
+
+            lay.header("Export GIF");
+            lay.num("plays", "x", &mut self.gif_plays, 1, 0, None);
+
+            lay.header("Export sheet");
+            lay.toggle_prop("layer only", &mut self.sheet_layer);
+            let layout = match self.sheet_layout {
+                Layout::Row => "row",
+                Layout::Column => "column",
+                Layout::Grid(_) => "grid",
+            };
+            if lay.text_button("layout", layout) {
+                self.sheet_layout = match self.sheet_layout {
+                    Layout::Row => Layout::Column,
+                    Layout::Column => Layout::Grid(0),
+                    Layout::Grid(_) => Layout::Row,
+                };
+            }
+            if let Layout::Grid(_) = self.sheet_layout {
+                lay.num("columns", "#", &mut self.sheet_columns, 1, 0, None);
+            }
+            lay.num("padding", "px", &mut self.sheet_padding, 1, 0, None);
+            lay.num("extrude", "px", &mut self.sheet_extrude, 1, 0, None);
+
+            lay.header("Extract palette");
+            lay.num("colors", "#", &mut self.extract_count, 1, 0, 256);
+            lay.toggle_prop("k-means", &mut self.extract_kmeans);
+            if lay.toggle_prop("sort hue", &mut self.sort_hue) && self.sort_hue {
+                self.sort_luminance = false;
+            }
+            if lay.toggle_prop("sort luma", &mut self.sort_luminance) && self.sort_luminance {
+                self.sort_hue = false;
             }
         }
         Flow::auto(1.0) => |ctx| {
@@ -493,6 +1044,7 @@ impl App {
                 let start = r.min;
 
                 let transparent = self.editor.transparent();
+                let mut dropped = None;
 
                 let w = (r.dx() as usize) / WH;
                 for i in 0..256 {
@@ -504,7 +1056,18 @@ impl App {
                     let r = Rect::from_min_dim(start + min.cast().unwrap(), dim);
                     let color = self.editor.pal(i as u8);
 
-                    if BTN.behavior(&ctx.sub_rect(r), &mut self.state, &mut ()) {
+                    let swatch = ctx.sub_rect(r);
+                    if swatch.is_cursor_hovering() {
+                        if swatch.was_pressed() {
+                            self.pal_drag = Some(i as u8);
+                        }
+                        match self.pal_drag {
+                            Some(from) if swatch.was_released() && from != i as u8 =>
+                                dropped = Some((from, i as u8)),
+                            _ => (),
+                        }
+                    }
+                    if BTN.behavior(&swatch, &mut self.state, &mut ()) {
                         self.editor.color = i as u8;
                     }
                     if self.editor.color == i as u8 {
@@ -523,12 +1086,135 @@ impl App {
                         ctx.quad(rgba(color), r);
                     }
                 }
+
+                if ctx.was_released() {
+                    self.pal_drag = None;
+                }
+                if let Some((from, to)) = dropped {
+                    self.drop_entry(from, to);
+                }
             }
             });
         }
         });
     }
 
+    /// RGB and HSV sliders and a hex field for the selected palette entry.
+    fn palette_editor(&mut self, lay: &mut EditorLayout) {
+        use draw::color::{to_hsv, from_hsv};
+        use format::{to_rgba, from_rgba};
+
+        let index = self.editor.color;
+        let color = self.editor.pal(index);
+        let [r, g, b, a] = to_rgba(color);
+        let mut changed = None;
+
+        lay.header(&format!("Color #{}", index));
+
+        let mut rgb = [r as f32, g as f32, b as f32];
+        for (i, name) in ["R", "G", "B"].iter().enumerate() {
+            if lay.slider(&format!("{} {}", name, rgb[i] as u8), &mut rgb[i], 0.0, 255.0) {
+                let u = |v: f32| v.round() as u8;
+                changed = Some(from_rgba(u(rgb[0]), u(rgb[1]), u(rgb[2]), a));
+            }
+        }
+
+        let (mut h, mut s, mut v) = if self.pal_hsv.0 == color { self.pal_hsv.1 } else { to_hsv(color) };
+        let mut update = false;
+        update |= lay.slider(&format!("H {}", h as u32), &mut h, 0.0, 360.0);
+        update |= lay.slider(&format!("S {}", (s * 100.0) as u32), &mut s, 0.0, 1.0);
+        update |= lay.slider(&format!("V {}", (v * 100.0) as u32), &mut v, 0.0, 1.0);
+        if update {
+            let c = from_hsv(h, s, v, a);
+            self.pal_hsv = (c, (h, s, v));
+            changed = Some(c);
+        }
+
+        let text = match self.hex {
+            Some((i, ref text)) if i == index => format!("{}_", text),
+            _ => format!("{:06X}", color >> 8),
+        };
+        if lay.text_button("hex", &text) {
+            self.hex = Some((index, String::new()));
+        }
+
+        if let Some(c) = changed {
+            self.editor.preview_pal(index, c);
+        }
+
+        lay.header(&format!("Ramp from #{}", index));
+        lay.num("to", "#", &mut self.ramp_end, 1, 0, 255);
+        lay.num("count", "", &mut self.ramp_count, 1, 0, 256);
+        lay.num("hue shift", "deg", &mut self.ramp_shift, 5, 0, 90);
+        let space = match self.ramp_space {
+            Space::Rgb => "RGB",
+            Space::Hsv => "HSV",
+            Space::Oklab => "OKLab",
+            Space::Lab => "CIELAB",
+        };
+        if lay.text_button("space", space) {
+            self.ramp_space = match self.ramp_space {
+                Space::Rgb => Space::Hsv,
+                Space::Hsv => Space::Oklab,
+                Space::Oklab => Space::Lab,
+                Space::Lab => Space::Rgb,
+            };
+        }
+        if lay.text_button("", "make ramp") {
+            self.make_ramp();
+        }
+
+        if self.convert.is_some() {
+            self.convert_panel(lay);
+        }
+    }
+
+    /// Options of a pending palette conversion, the canvas shows the result meanwhile.
+    fn convert_panel(&mut self, lay: &mut EditorLayout) {
+        let mut update = false;
+
+        lay.header("Convert palette");
+        let mut ciede = self.convert_opts.distance == Distance::Ciede2000;
+        if lay.toggle_prop("CIEDE2000", &mut ciede) {
+            self.convert_opts.distance = if ciede { Distance::Ciede2000 } else { Distance::Oklab };
+            update = true;
+        }
+
+        lay.num("override", "#", &mut self.override_from, 1, 0, 255);
+        lay.num("to", "#", &mut self.override_to, 1, 0, 255);
+        if lay.text_button("", "add override") {
+            let (from, to) = (self.override_from as u8, self.override_to as u8);
+            self.convert_opts.overrides.insert(from, to);
+            update = true;
+        }
+        let count = format!("{} set, clear", self.convert_opts.overrides.len());
+        if lay.text_button("overrides", &count) {
+            self.convert_opts.overrides.clear();
+            update = true;
+        }
+
+        if update {
+            self.update_convert();
+        }
+        if lay.text_button("", "apply") {
+            self.apply_convert();
+        }
+        if lay.text_button("", "cancel") {
+            self.convert = None;
+        }
+    }
+
+    /// Blends the selected entry into the ramp end, one step in the history.
+    pub fn make_ramp(&mut self) {
+        use format::palette::{ramp, Ramp};
+
+        let (start, end) = (self.editor.color, self.ramp_end as u8);
+        let count = self.ramp_count as usize;
+        let opts = Ramp { space: self.ramp_space, hue_shift: self.ramp_shift as f32 };
+        println!("make_ramp: {} -> {} count: {} {:?}", start, end, count, opts);
+        self.editor.rewrite(|m| ramp(&mut m.palette, start, end, count, opts));
+    }
+
     fn panel(&mut self, ctx: ui::Context<Canvas>) {
         ctx.quad(TIMELINE_BG, ctx.rect());
 
@@ -555,12 +1241,16 @@ impl App {
 
     fn statusbar(&mut self, ctx: ui::Context<Canvas>) {
         ctx.quad(STATUSBAR_BG, ctx.rect());
-        let text = format!("zoom: {}  #{:<3}", self.editor.zoom(), self.editor.color);
+        let text = {
+            let m = self.editor.image.as_receiver();
+            format!("zoom: {}  #{:<3}  frame: {}/{}",
+                self.editor.zoom(), self.editor.color, m.frame + 1, m.frame_count())
+        };
         ctx.label(0.01, 0.5, WHITE, &text);
 
         if self.in_widget {
             let text = format!("[{:>+5} {:<+5}]", self.mouse.x, self.mouse.y);
-            ctx.label(0.2, 0.5, WHITE, &text);
+            ctx.label(0.3, 0.5, WHITE, &text);
         }
 
         let now = Instant::now();
@@ -655,6 +1345,7 @@ impl App {
         ctx.quad(MENUBAR_BG, ctx.rect());
         self.file_menu_id = ctx.reserve_widget_id();
         self.brush_menu_id = ctx.reserve_widget_id();
+        let was_open = self.menubar.open_root.map(|(id, _)| id);
         MENUBAR.run(&ctx, &mut self.state, &mut self.menubar, &[
             (self.file_menu_id, "File"),
             (ctx.reserve_widget_id(), "Edit"),
@@ -663,23 +1354,58 @@ impl App {
             (ctx.reserve_widget_id(), "Tools"),
             (ctx.reserve_widget_id(), "Help"),
         ]);
+
+        // recent files are checked once when the File menu opens, not every frame
+        let open = self.menubar.open_root.map(|(id, _)| id);
+        if open != was_open && open == Some(self.file_menu_id) {
+            self.recent.retain_existing();
+        }
     }
 
     fn second_menubar(&mut self, ctx: ui::Context<Canvas>) {
         let mut exit = true;
         match self.menubar.open_root {
         Some((id, base_rect)) if id == self.file_menu_id => {
-            match MENU.run(&ctx, &mut self.state, id, base_rect, &FILE_ITEMS) {
+            let labels: Vec<String> = self.recent.files().iter()
+                .map(|file| Path::new(file).file_name()
+                    .map_or(file.clone(), |name| name.to_string_lossy().into_owned()))
+                .collect();
+            let recent: Vec<_> = labels.iter().enumerate()
+                .map(|(i, label)| Item::Text(Command::Recent(i), label.as_str(), ""))
+                .collect();
+            let items = file_items(&recent);
+            match MENU.run(&ctx, &mut self.state, id, base_rect, &items) {
                 MenuEvent::Nothing => exit = false,
                 MenuEvent::Exit => (),
                 MenuEvent::Clicked(Command::Open) => {
                     if let Some(name) = ::open::open_file() {
-                        println!("open_file: {}", name);
-                        if let Some(image) = ::open::load_sprite(name) {
-                            self.editor.recreate(image);
-                        }
+                        self.open(name);
+                    }
+                }
+                MenuEvent::Clicked(Command::Recent(i)) => {
+                    let name = self.recent.files()[i].clone();
+                    if !self.open(name.clone()) {
+                        self.recent.remove(&name);
                     }
                 }
+                MenuEvent::Clicked(Command::ImportSheet) => self.import_sheet(),
+                MenuEvent::Clicked(Command::Save) => self.save(false),
+                MenuEvent::Clicked(Command::SaveAs) => self.save(true),
+                MenuEvent::Clicked(Command::Export) => self.export(Source::Composite),
+                MenuEvent::Clicked(Command::ExportLayer) => {
+                    let layer = self.editor.image.as_receiver().layer;
+                    self.export(Source::Layer(layer));
+                }
+                MenuEvent::Clicked(Command::ExportSheet) => self.export_sheet(),
+                MenuEvent::Clicked(Command::ExportScaled) => self.export_scaled(),
+                MenuEvent::Clicked(Command::ExportTileset) => self.export_tileset(),
+                MenuEvent::Clicked(Command::ExtractPalette) => self.extract_palette(),
+                MenuEvent::Clicked(Command::CompactPalette) => self.compact_palette(),
+                MenuEvent::Clicked(Command::ConvertPalette) => self.convert_palette(),
+                MenuEvent::Clicked(Command::SortPalette(by)) => self.sort_palette(by),
+                MenuEvent::Clicked(Command::ImportPalette) => self.import_palette(Import::KeepIndices),
+                MenuEvent::Clicked(Command::RemapPalette) => self.import_palette(Import::Remap),
+                MenuEvent::Clicked(Command::ExportPalette) => self.export_palette(),
                 MenuEvent::Clicked(Command::Quit) => self.quit = true,
                 MenuEvent::Clicked(id) => {
                     println!("click: {:?}", id);
@@ -701,5 +1427,55 @@ impl App {
         if exit {
             self.menubar.open_root = None;
         }
+
+        self.error_message(&ctx);
+        self.recovery_prompt(&ctx);
+    }
+
+    fn error_message(&mut self, ctx: &ui::Context<Canvas>) {
+        let text = match self.error {
+            Some(ref err) => format!("error: {}", err),
+            None => return,
+        };
+
+        let size = ctx.measure_text(&text) + Vector2::new(32.0, 16.0);
+        let ctx = ctx.align(Vector2::new(0.5, 0.15), size);
+        ctx.quad(ERROR_BG, ctx.rect());
+        ctx.label(0.5, 0.5, WHITE, &text);
+
+        // any click on the message dismisses it
+        if BTN.behavior(&ctx, &mut self.state, &mut ()) {
+            self.error = None;
+        }
+    }
+
+    fn recovery_prompt(&mut self, ctx: &ui::Context<Canvas>) {
+        let text = match self.recovery.first() {
+            Some(snapshot) => format!("recover unsaved changes to {}?", snapshot.name()),
+            None => return,
+        };
+
+        let size = ctx.measure_text(&text) + Vector2::new(32.0, 16.0);
+        let size = Vector2::new(size.x.max(200.0), size.y * 2.0);
+        let ctx = ctx.align(Vector2::new(0.5, 0.3), size);
+        ctx.quad(BAR_BG, ctx.rect());
+
+        let (message, buttons) = ctx.split_y(0.5);
+        message.label(0.5, 0.5, WHITE, &text);
+
+        let (restore, discard) = buttons.split_x(0.5);
+        let restore = restore.sub_rect(restore.rect().pad(2.0));
+        let discard = discard.sub_rect(discard.rect().pad(2.0));
+        let do_restore = BTN.behavior(&restore, &mut self.state, &mut ());
+        let do_discard = BTN.behavior(&discard, &mut self.state, &mut ());
+        restore.label(0.5, 0.5, WHITE, "Restore");
+        discard.label(0.5, 0.5, WHITE, "Discard");
+
+        if do_restore {
+            let snapshot = self.recovery.remove(0);
+            self.restore(snapshot);
+        } else if do_discard {
+            self.recovery.remove(0).remove();
+        }
     }
 }
@@ -0,0 +1,221 @@
+use std::fs;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use format::native;
+use tool::Receiver;
+
+/// Committed changes between two snapshots.
+pub const COMMITS: usize = 20;
+/// Unsaved changes older than this are snapshotted regardless of their count.
+pub const INTERVAL_SECS: u64 = 180;
+
+const UNTITLED: &str = "untitled";
+/// Marks snapshots moved out of the way of the running session.
+const RECOVERED: &str = "recovered";
+
+/// Directory for snapshots of unsaved work.
+pub fn recovery_dir() -> Option<PathBuf> {
+    ::recent::config_dir().map(|dir| dir.join("recovery"))
+}
+
+/// A snapshot left behind by a session that didn't save.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub path: PathBuf,
+    /// The document it belongs to, `None` if it was never saved.
+    pub original: Option<String>,
+}
+
+impl Snapshot {
+    pub fn name(&self) -> String {
+        self.original.as_ref()
+            .and_then(|file| Path::new(file).file_name())
+            .map_or(UNTITLED.to_string(), |name| name.to_string_lossy().into_owned())
+    }
+
+    pub fn remove(&self) {
+        let _ = fs::remove_file(&self.path);
+        let _ = fs::remove_file(self.path.with_extension("path"));
+    }
+}
+
+/// Periodically writes the document in the native format
+/// so a crash loses at most a few minutes of work.
+pub struct Autosave {
+    dir: Option<PathBuf>,
+    last: Instant,
+    saved: usize,
+}
+
+impl Autosave {
+    pub fn new() -> Self {
+        Self::with_dir(recovery_dir())
+    }
+
+    pub fn with_dir(dir: Option<PathBuf>) -> Self {
+        Self { dir, last: Instant::now(), saved: 0 }
+    }
+
+    fn snapshot_path(&self, filename: Option<&str>) -> Option<PathBuf> {
+        let name: String = match filename {
+            Some(file) => file.chars()
+                .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+                .collect(),
+            None => UNTITLED.to_string(),
+        };
+        self.dir.as_ref().map(|dir| dir.join(format!("{}.{}", name, native::EXTENSION)))
+    }
+
+    /// Starts counting again for a document that was just opened or created.
+    pub fn reset(&mut self) {
+        self.last = Instant::now();
+        self.saved = 0;
+    }
+
+    /// Writes a snapshot once enough changes piled up or enough time passed.
+    pub fn update(&mut self, sprite: &Receiver, filename: Option<&str>, changes: usize) {
+        if changes == self.saved {
+            return;
+        }
+        if changes.wrapping_sub(self.saved) < COMMITS && self.last.elapsed().as_secs() < INTERVAL_SECS {
+            return;
+        }
+
+        self.last = Instant::now();
+        self.saved = changes;
+        if let Err(err) = self.write(sprite, filename) {
+            println!("error: autosave failed: {}", err);
+        }
+    }
+
+    fn write(&self, sprite: &Receiver, filename: Option<&str>) -> io::Result<()> {
+        let path = match self.snapshot_path(filename) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // write aside first, a crash halfway must not eat the previous snapshot
+        let tmp = path.with_extension("tmp");
+        {
+            let mut w = BufWriter::new(fs::File::create(&tmp)?);
+            native::write(&mut w, sprite)?;
+        }
+        fs::write(path.with_extension("path"), filename.unwrap_or(""))?;
+        fs::rename(tmp, path)
+    }
+
+    /// The document was saved as `filename`, its snapshot is stale now.
+    pub fn saved(&mut self, filename: Option<&str>, changes: usize) {
+        self.last = Instant::now();
+        self.saved = changes;
+        if let Some(path) = self.snapshot_path(filename) {
+            Snapshot { path, original: None }.remove();
+        }
+    }
+
+    /// Snapshots newer than the file they belong to, newest first.
+    pub fn pending(&self) -> Vec<Snapshot> {
+        let entries = match self.dir.as_ref().and_then(|dir| fs::read_dir(dir).ok()) {
+            Some(entries) => entries,
+            None => return Vec::new(),
+        };
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+
+        let mut pending: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == native::EXTENSION))
+            .filter_map(|path| {
+                let original = fs::read_to_string(path.with_extension("path")).ok()?;
+                let original = if original.is_empty() { None } else { Some(original) };
+                let time = modified(&path)?;
+                let newer = original.as_ref()
+                    .and_then(|file| modified(Path::new(file)))
+                    .map_or(true, |saved| time > saved);
+                let snapshot = Snapshot { path, original };
+                if newer {
+                    Some((time, snapshot))
+                } else {
+                    snapshot.remove();
+                    None
+                }
+            })
+            .collect();
+
+        pending.sort_by(|a, b| b.0.cmp(&a.0));
+        pending.into_iter().map(|(_, snapshot)| snapshot).collect()
+    }
+
+    /// Pending snapshots, renamed so the running session's autosaves
+    /// can't overwrite them before the user decides what to do.
+    pub fn recover(&self) -> Vec<Snapshot> {
+        self.pending().into_iter().map(set_aside).collect()
+    }
+}
+
+fn set_aside(snapshot: Snapshot) -> Snapshot {
+    let stem = match snapshot.path.file_stem() {
+        Some(stem) => stem.to_string_lossy().into_owned(),
+        None => return snapshot,
+    };
+    if stem.contains(&format!(".{}", RECOVERED)) {
+        return snapshot;
+    }
+
+    let free = (0..).map(|n| {
+        let suffix = if n == 0 { String::new() } else { n.to_string() };
+        snapshot.path.with_file_name(format!("{}.{}{}.{}", stem, RECOVERED, suffix, native::EXTENSION))
+    }).find(|path| !path.exists()).unwrap();
+
+    let renamed = fs::rename(&snapshot.path, &free)
+        .and_then(|()| fs::rename(snapshot.path.with_extension("path"), free.with_extension("path")));
+    match renamed {
+        Ok(()) => Snapshot { path: free, original: snapshot.original },
+        Err(err) => {
+            println!("error: can't set aside {}: {}", snapshot.path.display(), err);
+            snapshot
+        }
+    }
+}
+
+#[test]
+fn snapshot_after_commits() {
+    use std::env;
+
+    let dir = env::temp_dir().join(format!("asprite-recovery-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut sprite = Receiver::new("test", 4, 4);
+    sprite.add_layer("a");
+
+    let mut autosave = Autosave::with_dir(Some(dir.clone()));
+    autosave.update(&sprite, None, COMMITS - 1);
+    assert!(autosave.pending().is_empty());
+
+    autosave.update(&sprite, None, COMMITS);
+    let pending = autosave.pending();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].original, None);
+    assert_eq!(pending[0].name(), UNTITLED);
+
+    autosave.saved(None, COMMITS);
+    assert!(autosave.pending().is_empty());
+
+    // a crashed session's snapshot survives the next session's autosaves
+    autosave.update(&sprite, None, 2 * COMMITS);
+    let recovered = autosave.recover();
+    assert_eq!(recovered.len(), 1);
+    assert!(recovered[0].path.exists());
+    sprite.name = "newer".to_string();
+    autosave.update(&sprite, None, 3 * COMMITS);
+    let old = native::read(&mut &fs::read(&recovered[0].path).unwrap()[..]).unwrap();
+    assert_eq!(old.name, "test");
+    assert_eq!(autosave.recover().len(), 2);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
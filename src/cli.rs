@@ -0,0 +1,169 @@
+use std::io;
+
+use format::{self, Source, Error};
+use format::palette::Import;
+use format::gif::Loop;
+use format::sheet::Layout;
+use tool::Receiver;
+
+pub const USAGE: &str = "\
+usage: asprite INPUT -o OUTPUT [options]
+       asprite build [MANIFEST] [--force]
+
+Without arguments the editor window opens.
+
+options:
+    -o, --output FILE   write the result, the format comes from the extension
+    --palette FILE      replace the palette keeping pixel indices
+    --remap             with --palette, move pixels to the nearest new colour
+    --scale N           nearest-neighbour upscale by N
+    --layer N           export only layer N instead of the composite
+    --sheet LAYOUT      pack all frames into a PNG sheet: row, column, grid or grid=COLUMNS
+    --padding N         pixels between sheet cells
+    --extrude N         edge pixels repeated around every sheet cell
+    --loop N            play a GIF N times, 0 loops forever (default)
+
+build processes every target of the manifest (res/res.toml by default)
+whose output is older than its inputs, --force rebuilds everything.
+";
+
+pub struct Args {
+    pub input: String,
+    pub output: String,
+    pub palette: Option<String>,
+    pub remap: bool,
+    pub scale: usize,
+    pub source: Source,
+    pub sheet: Option<format::sheet::Options>,
+    pub repeat: Loop,
+}
+
+pub fn parse_layout(s: &str) -> Result<Layout, String> {
+    match s {
+        "row" => Ok(Layout::Row),
+        "column" => Ok(Layout::Column),
+        "grid" => Ok(Layout::Grid(0)),
+        s if s.starts_with("grid=") => s[5..].parse()
+            .map(Layout::Grid)
+            .map_err(|_| format!("bad column count: {}", &s[5..])),
+        s => Err(format!("unknown sheet layout: {}", s)),
+    }
+}
+
+pub fn parse(args: &[String]) -> Result<Args, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut palette = None;
+    let mut remap = false;
+    let mut scale = 1;
+    let mut source = Source::Composite;
+    let mut layout = None;
+    let mut sheet = format::sheet::Options::default();
+    let mut repeat = Loop::Forever;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().cloned().ok_or_else(|| format!("missing value for {}", arg));
+        let number = |v: String| v.parse::<usize>().map_err(|_| format!("bad number: {}", v));
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?),
+            "--palette" => palette = Some(value()?),
+            "--remap" => remap = true,
+            "--scale" => scale = number(value()?)?.max(1),
+            "--layer" => source = Source::Layer(number(value()?)?),
+            "--sheet" => layout = Some(parse_layout(&value()?)?),
+            "--padding" => sheet.padding = number(value()?)?,
+            "--extrude" => sheet.extrude = number(value()?)?,
+            "--loop" => repeat = value()?.parse().map(Loop::plays).map_err(|_| "bad loop count".to_string())?,
+            s if s.starts_with('-') => return Err(format!("unknown option: {}", s)),
+            s if input.is_none() => input = Some(s.to_string()),
+            s => return Err(format!("unexpected argument: {}", s)),
+        }
+    }
+
+    let sheet = layout.map(|layout| format::sheet::Options { layout, source, .. sheet });
+    Ok(Args {
+        input: input.ok_or("missing input file")?,
+        output: output.ok_or("missing output file")?,
+        palette,
+        remap,
+        scale,
+        source,
+        sheet,
+        repeat,
+    })
+}
+
+pub fn process(args: &Args) -> Result<(), Error> {
+    let mut sprite: Receiver = ::open::load_sprite(&args.input)?;
+
+    if let Source::Layer(layer) = args.source {
+        if layer >= sprite.data.len() {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::NotFound, "no such layer")));
+        }
+    }
+
+    if let Some(ref filename) = args.palette {
+        let palette = ::open::load_palette(filename)?;
+        let mode = if args.remap { Import::Remap } else { Import::KeepIndices };
+        format::palette::apply(&mut sprite, &palette, mode);
+    }
+
+    if args.scale > 1 {
+        sprite = format::scale(&sprite, args.scale);
+    }
+
+    if let Some(ref opts) = args.sheet {
+        ::open::export_sheet(&args.output, &sprite, opts)?;
+    } else if ::open::is_native(&args.output) {
+        ::open::save_sprite(&args.output, &sprite)?;
+    } else {
+        ::open::export_sprite(&args.output, &sprite, args.source, args.repeat)?;
+    }
+    Ok(())
+}
+
+/// Runs the command line, returns the exit code.
+pub fn run(args: &[String]) -> i32 {
+    if args.first().map_or(false, |arg| arg == "build") {
+        return ::pipeline::run(&args[1..]);
+    }
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print!("{}", USAGE);
+        return 0;
+    }
+
+    let args = match parse(args) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            return 2;
+        }
+    };
+
+    match process(&args) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("error: {}: {}", args.input, err);
+            1
+        }
+    }
+}
+
+#[test]
+fn parse_args() {
+    let args: Vec<String> = ["hero.aspr", "--sheet", "grid=4", "--layer", "1", "-o", "hero.png", "--scale", "2", "--loop", "3"]
+        .iter().map(|s| s.to_string()).collect();
+    let args = parse(&args).unwrap();
+    assert_eq!(args.input, "hero.aspr");
+    assert_eq!(args.output, "hero.png");
+    assert_eq!(args.scale, 2);
+    assert_eq!(args.source, Source::Layer(1));
+    assert_eq!(args.repeat, Loop::Times(2));
+    let sheet = args.sheet.unwrap();
+    assert_eq!(sheet.layout, Layout::Grid(4));
+    assert_eq!(sheet.source, Source::Layer(1));
+
+    let args: Vec<String> = vec!["a.png".to_string(), "--bogus".to_string()];
+    assert!(parse(&args).is_err());
+}
@@ -0,0 +1,291 @@
+// Colour space helpers for 0xRRGGBBAA colours.
+
+fn channels(c: u32) -> (f32, f32, f32) {
+    let f = |shift: u32| ((c >> shift) & 0xFF) as f32 / 255.0;
+    (f(24), f(16), f(8))
+}
+
+fn pack(r: f32, g: f32, b: f32, a: u8) -> u32 {
+    let u = |v: f32| (v.max(0.0).min(1.0) * 255.0 + 0.5) as u32;
+    u(r) << 24 | u(g) << 16 | u(b) << 8 | a as u32
+}
+
+/// Hue in degrees `0..360`, saturation and value in `0..1`.
+pub fn to_hsv(c: u32) -> (f32, f32, f32) {
+    let (r, g, b) = channels(c);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let d = max - min;
+
+    let h = if d == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / d)
+    } else if max == g {
+        60.0 * ((b - r) / d + 2.0)
+    } else {
+        60.0 * ((r - g) / d + 4.0)
+    };
+    let h = if h < 0.0 { h + 360.0 } else { h };
+    let s = if max == 0.0 { 0.0 } else { d / max };
+    (h, s, max)
+}
+
+pub fn from_hsv(h: f32, s: f32, v: f32, alpha: u8) -> u32 {
+    let h = ((h % 360.0) + 360.0) % 360.0 / 60.0;
+    let c = v * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = v - c;
+    pack(r + m, g + m, b + m, alpha)
+}
+
+/// Relative luminance of the gamma encoded colour, Rec. 709 weights.
+pub fn luminance(c: u32) -> f32 {
+    let (r, g, b) = channels(c);
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+fn to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn from_linear(c: f32) -> f32 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+fn linear(c: u32) -> (f32, f32, f32) {
+    let (r, g, b) = channels(c);
+    (to_linear(r), to_linear(g), to_linear(b))
+}
+
+fn pack_linear(r: f32, g: f32, b: f32, alpha: u8) -> u32 {
+    pack(from_linear(r), from_linear(g), from_linear(b), alpha)
+}
+
+/// Björn Ottosson's OKLab, lightness in `0..1`.
+pub fn to_oklab(c: u32) -> (f32, f32, f32) {
+    let (r, g, b) = linear(c);
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    (
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    )
+}
+
+pub fn from_oklab(l: f32, a: f32, b: f32, alpha: u8) -> u32 {
+    let cube = |v: f32| v * v * v;
+    let l_ = cube(l + 0.3963377774 * a + 0.2158037573 * b);
+    let m_ = cube(l - 0.1055613458 * a - 0.0638541728 * b);
+    let s_ = cube(l - 0.0894841775 * a - 1.2914855480 * b);
+    pack_linear(
+        4.0767416621 * l_ - 3.3077115913 * m_ + 0.2309699292 * s_,
+        -1.2684380046 * l_ + 2.6097574011 * m_ - 0.3413193965 * s_,
+        -0.0041960863 * l_ - 0.7034186147 * m_ + 1.7076147010 * s_,
+        alpha,
+    )
+}
+
+// CIELAB relative to the D65 white point.
+const WHITE: (f32, f32, f32) = (0.95047, 1.0, 1.08883);
+const DELTA: f32 = 6.0 / 29.0;
+
+/// CIE L*a*b*, lightness in `0..100`.
+pub fn to_lab(c: u32) -> (f32, f32, f32) {
+    let (r, g, b) = linear(c);
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / WHITE.0;
+    let y = (0.2126729 * r + 0.7151522 * g + 0.0721750 * b) / WHITE.1;
+    let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / WHITE.2;
+
+    let f = |t: f32| if t > DELTA * DELTA * DELTA {
+        t.cbrt()
+    } else {
+        t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+pub fn from_lab(l: f32, a: f32, b: f32, alpha: u8) -> u32 {
+    let fy = (l + 16.0) / 116.0;
+    let (fx, fz) = (fy + a / 500.0, fy - b / 200.0);
+
+    let f = |t: f32| if t > DELTA { t * t * t } else { 3.0 * DELTA * DELTA * (t - 4.0 / 29.0) };
+    let (x, y, z) = (f(fx) * WHITE.0, f(fy) * WHITE.1, f(fz) * WHITE.2);
+    pack_linear(
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+        alpha,
+    )
+}
+
+/// Colour space for blending.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Space {
+    Rgb,
+    /// Hue goes the short way around the wheel.
+    Hsv,
+    Oklab,
+    Lab,
+}
+
+/// Signed hue difference from `a` to `b` in `-180..180`.
+pub fn hue_delta(a: f32, b: f32) -> f32 {
+    ((b - a) % 360.0 + 540.0) % 360.0 - 180.0
+}
+
+/// Colour `t` of the way from `a` to `b`, alpha is blended linearly.
+pub fn mix(a: u32, b: u32, t: f32, space: Space) -> u32 {
+    let lerp = |x: f32, y: f32| x + (y - x) * t;
+    let alpha = lerp((a & 0xFF) as f32, (b & 0xFF) as f32).round() as u8;
+    match space {
+        Space::Rgb => {
+            let (ar, ag, ab) = channels(a);
+            let (br, bg, bb) = channels(b);
+            pack(lerp(ar, br), lerp(ag, bg), lerp(ab, bb), alpha)
+        }
+        Space::Hsv => {
+            let (ah, as_, av) = to_hsv(a);
+            let (bh, bs, bv) = to_hsv(b);
+            // greys have no hue of their own, take the other one
+            let ah = if as_ == 0.0 { bh } else { ah };
+            let bh = if bs == 0.0 { ah } else { bh };
+            from_hsv(ah + hue_delta(ah, bh) * t, lerp(as_, bs), lerp(av, bv), alpha)
+        }
+        Space::Oklab => {
+            let (al, aa, ab) = to_oklab(a);
+            let (bl, ba, bb) = to_oklab(b);
+            from_oklab(lerp(al, bl), lerp(aa, ba), lerp(ab, bb), alpha)
+        }
+        Space::Lab => {
+            let (al, aa, ab) = to_lab(a);
+            let (bl, ba, bb) = to_lab(b);
+            from_lab(lerp(al, bl), lerp(aa, ba), lerp(ab, bb), alpha)
+        }
+    }
+}
+
+/// CIEDE2000 difference of two L*a*b* colours, from Sharma, Wu and Dalal.
+pub fn ciede2000(lab1: (f32, f32, f32), lab2: (f32, f32, f32)) -> f32 {
+    let ((l1, a1, b1), (l2, a2, b2)) = (lab1, lab2);
+    let pow7 = |v: f32| v.powi(7);
+    let (rad, deg) = (::std::f32::consts::PI / 180.0, 180.0 / ::std::f32::consts::PI);
+
+    let c_bar = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+    let g = 0.5 * (1.0 - (pow7(c_bar) / (pow7(c_bar) + pow7(25.0))).sqrt());
+    let (a1, a2) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1, c2) = ((a1 * a1 + b1 * b1).sqrt(), (a2 * a2 + b2 * b2).sqrt());
+    let hue = |b: f32, a: f32| if a == 0.0 && b == 0.0 { 0.0 } else { (b.atan2(a) * deg + 360.0) % 360.0 };
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+    let dl = l2 - l1;
+    let dc = c2 - c1;
+    let dh = if c1 * c2 == 0.0 { 0.0 } else { hue_delta(h1, h2) };
+    let dh = 2.0 * (c1 * c2).sqrt() * (dh / 2.0 * rad).sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * ((h_bar - 30.0) * rad).cos()
+        + 0.24 * (2.0 * h_bar * rad).cos()
+        + 0.32 * ((3.0 * h_bar + 6.0) * rad).cos()
+        - 0.20 * ((4.0 * h_bar - 63.0) * rad).cos();
+    let theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let rc = 2.0 * (pow7(c_bar) / (pow7(c_bar) + pow7(25.0))).sqrt();
+    let sl = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let sc = 1.0 + 0.045 * c_bar;
+    let sh = 1.0 + 0.015 * c_bar * t;
+    let rt = -(2.0 * theta * rad).sin() * rc;
+
+    let (l, c, h) = (dl / sl, dc / sc, dh / sh);
+    (l * l + c * c + h * h + rt * c * h).max(0.0).sqrt()
+}
+
+/// Perceptual colour difference.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Distance {
+    /// Euclidean distance in OKLab, cheap and good enough for most palettes.
+    Oklab,
+    Ciede2000,
+}
+
+/// How different two colours look, alpha is ignored.
+pub fn distance(a: u32, b: u32, metric: Distance) -> f32 {
+    match metric {
+        Distance::Oklab => {
+            let ((l1, a1, b1), (l2, a2, b2)) = (to_oklab(a), to_oklab(b));
+            ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)).sqrt()
+        }
+        Distance::Ciede2000 => ciede2000(to_lab(a), to_lab(b)),
+    }
+}
+
+#[test]
+fn hsv_round_trip() {
+    for &c in &[0xFF0000_FF, 0x00FF00_FF, 0x0000FF_FF, 0x808080_FF, 0xC44448_FF, 0x2874C4_80] {
+        let (h, s, v) = to_hsv(c);
+        assert_eq!(from_hsv(h, s, v, c as u8), c, "{:08x}", c);
+    }
+    assert_eq!(to_hsv(0x00FF00_FF), (120.0, 1.0, 1.0));
+}
+
+#[test]
+fn lab_round_trip() {
+    for &c in &[0xFF0000_FF, 0x00FF00_FF, 0x0000FF_FF, 0x808080_FF, 0xC44448_FF, 0xFFFFFF_FF] {
+        let (l, a, b) = to_oklab(c);
+        assert_eq!(from_oklab(l, a, b, 0xFF), c, "oklab {:08x}", c);
+        let (l, a, b) = to_lab(c);
+        assert_eq!(from_lab(l, a, b, 0xFF), c, "lab {:08x}", c);
+    }
+    let (l, _, _) = to_lab(0xFFFFFF_FF);
+    assert!((l - 100.0).abs() < 0.01);
+}
+
+#[test]
+fn mix_spaces() {
+    assert_eq!(mix(0x000000_FF, 0xFFFFFF_FF, 0.5, Space::Rgb), 0x808080_FF);
+    // red to blue the short way passes magenta
+    assert_eq!(mix(0xFF0000_FF, 0x0000FF_FF, 0.5, Space::Hsv), 0xFF00FF_FF);
+    for &space in &[Space::Rgb, Space::Hsv, Space::Oklab, Space::Lab] {
+        assert_eq!(mix(0x204060_FF, 0xC08040_FF, 0.0, space), 0x204060_FF, "{:?}", space);
+        assert_eq!(mix(0x204060_FF, 0xC08040_FF, 1.0, space), 0xC08040_FF, "{:?}", space);
+    }
+}
+
+#[test]
+fn ciede2000_pairs() {
+    // test data from Sharma, Wu and Dalal
+    let pairs = [
+        ((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+        ((50.0, -1.3802, -84.2814), (50.0, 0.0, -82.7485), 1.0000),
+        ((50.0, 2.5, 0.0), (50.0, 0.0, -2.5), 4.3065),
+        ((50.0, 2.5, 0.0), (73.0, 25.0, -18.0), 27.1492),
+        ((22.7233, 20.0904, -46.6940), (23.0331, 14.9730, -42.5619), 2.0373),
+    ];
+    for &(a, b, expected) in &pairs {
+        let d = ciede2000(a, b);
+        assert!((d - expected).abs() < 1e-3, "{:?} {:?}: {} != {}", a, b, d, expected);
+        assert!((ciede2000(b, a) - expected).abs() < 1e-3);
+    }
+    assert_eq!(distance(0x123456_FF, 0x123456_00, Distance::Ciede2000), 0.0);
+}
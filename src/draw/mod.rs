@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+pub mod color;
 pub mod gradient;
 pub mod shape;
 pub mod view;
@@ -31,5 +32,5 @@ pub use self::canvas::{
     CanvasRead,
     CanvasWrite,
 };
-pub use self::palette::Palette;
+pub use self::palette::{Palette, XOR};
 pub use self::frame::Frame;
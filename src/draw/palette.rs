@@ -1,3 +1,4 @@
+use std::fmt;
 use std::ops::{
     Index,
     IndexMut,
@@ -21,6 +22,16 @@ impl<T: Copy> Palette<T> {
     }
 }
 
+impl<T: fmt::Debug> fmt::Debug for Palette<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Palette")
+            .field("map", &&self.map[..self.size.min(256)])
+            .field("size", &self.size)
+            .field("transparent", &self.transparent)
+            .finish()
+    }
+}
+
 impl<T> Index<u8> for Palette<T> {
     type Output = T;
     fn index(&self, idx: u8) -> &Self::Output {
@@ -46,9 +57,15 @@ impl<T> DerefMut for Palette<T> {
     }
 }
 
-/*
+/// Pairs every index with the most different colour it can get,
+/// for overlays that have to show on any pixel. Partners come from the
+/// used part of the palette and pair symmetrically, `xor[xor[i]] == i`,
+/// except for an odd last entry and indices past the used part.
 pub struct XOR([u8; 256]);
 
+/// Every pass is 256² comparisons, later passes rarely improve much.
+const XOR_PASSES: usize = 8;
+
 impl Index<u8> for XOR {
     type Output = u8;
     fn index(&self, idx: u8) -> &Self::Output {
@@ -57,21 +74,30 @@ impl Index<u8> for XOR {
 }
 
 impl XOR {
-    pub fn new() -> XOR { XOR([0u8; 256]) }
-
-    #[allow(overflowing_literals)]
-    pub fn compute<F>(&mut self, diff: F) where F: Fn(u8, u8) -> isize {
+    pub fn new() -> XOR {
+        let mut xor = XOR([0u8; 256]);
         for i in 0..256 {
-            self.0[i] = i as u8 ^1;
+            xor.0[i] = i as u8 ^ 1;
         }
-        loop {
+        xor
+    }
+
+    /// Pairs the entries below `size`, the others get their most different one there.
+    pub fn compute<F>(&mut self, size: usize, diff: F) where F: Fn(u8, u8) -> isize {
+        *self = XOR::new();
+        let size = size.max(1).min(256);
+        // entries swap partners among an even count
+        let even = size & !1;
+        for _ in 0..XOR_PASSES {
             // Find the smallest difference in the table
             // Try to pair these two colors better
             let mut found = false;
-            for idx in 0..256 {
+            for idx in 0..even {
+                let idx = idx as u8;
                 let mut improvement = 0;
                 let mut betterpair = idx;
-                for i in 0..256 {
+                for i in 0..even {
+                    let i = i as u8;
                     // diffs before the swap
                     let before = diff(idx, self[idx]) + diff(i, self[i]);
                     // diffs after the swap
@@ -89,9 +115,9 @@ impl XOR {
                     let i2 = self[idx];
 
                     self.0[betterpair as usize] = i2;
-                    self.0[i2 as usize] = betterpair as u8;
-                    self.0[idx as usize] = idx2 as u8;
-                    self.0[idx2 as usize] = idx as u8;
+                    self.0[i2 as usize] = betterpair;
+                    self.0[idx as usize] = idx2;
+                    self.0[idx2 as usize] = idx;
 
                     found = true;
                 }
@@ -100,6 +126,39 @@ impl XOR {
                 break;
             }
         }
+
+        for idx in even..256 {
+            let idx = idx as u8;
+            self.0[idx as usize] = (0..size)
+                .map(|i| i as u8)
+                .max_by_key(|&i| diff(idx, i))
+                .unwrap_or(0);
+        }
+    }
+}
+
+#[test]
+fn xor_pairs() {
+    // a dark and a light half, every colour should land in the other one
+    let diff = |a: u8, b: u8| ((a >= 128) != (b >= 128)) as isize;
+    let mut xor = XOR::new();
+    xor.compute(256, diff);
+    for i in 0..256 {
+        let i = i as u8;
+        assert_eq!(xor[xor[i]], i);
+        assert!(diff(i, xor[i]) == 1, "{} paired with {}", i, xor[i]);
+    }
+
+    // only the first five entries are in use
+    let diff = |a: u8, b: u8| (a as isize - b as isize).abs();
+    xor.compute(5, diff);
+    for i in 0..256 {
+        let i = i as u8;
+        assert!(xor[i] < 5, "{} paired with unused {}", i, xor[i]);
+    }
+    for i in 0..4 {
+        assert_eq!(xor[xor[i]], i);
     }
+    assert_eq!(xor[4], 0);
+    assert_eq!(xor[200], 0);
 }
-*/
@@ -0,0 +1,567 @@
+// Aseprite file format, see
+// https://github.com/aseprite/aseprite/blob/master/docs/ase-file-specs.md
+//
+// Only the indexed colour mode maps onto our sprites.
+
+use std::io::{self, Read, Write};
+use std::collections::HashMap;
+use byteorder::{LittleEndian as LE, ReadBytesExt, WriteBytesExt};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use draw::Frame;
+use tool::{Receiver, Layer};
+use super::{invalid, unsupported, from_rgba, to_rgba, read_bytes};
+
+const HEADER_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+
+const DEPTH_RGBA: u16 = 32;
+const DEPTH_GRAYSCALE: u16 = 16;
+const DEPTH_INDEXED: u16 = 8;
+
+const CHUNK_OLD_PALETTE: u16 = 0x0004;
+const CHUNK_OLD_PALETTE_64: u16 = 0x0011;
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_PALETTE: u16 = 0x2019;
+
+const LAYER_VISIBLE: u16 = 1;
+const LAYER_EDITABLE: u16 = 2;
+const LAYER_BACKGROUND: u16 = 8;
+
+const LAYER_NORMAL: u16 = 0;
+
+const CEL_RAW: u16 = 0;
+const CEL_LINKED: u16 = 1;
+const CEL_COMPRESSED: u16 = 2;
+
+fn skip<R: Read>(r: &mut R, count: u64) -> io::Result<()> {
+    io::copy(&mut r.take(count), &mut io::sink())?;
+    Ok(())
+}
+
+fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    w.write_u16::<LE>(s.len() as u16)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_str<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = r.read_u16::<LE>()? as usize;
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+#[derive(Clone)]
+struct Cel {
+    x: i16,
+    y: i16,
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Cel {
+    fn draw(&self, page: &mut Frame) {
+        for y in 0..self.height {
+            let py = self.y as isize + y as isize;
+            if py < 0 || py >= page.height as isize { continue }
+            for x in 0..self.width {
+                let px = self.x as isize + x as isize;
+                if px < 0 || px >= page.width as isize { continue }
+                page.page[px as usize + py as usize * page.width] = self.pixels[x + y * self.width];
+            }
+        }
+    }
+}
+
+/// Reads an indexed Aseprite file, every cel is placed onto a full-canvas page.
+/// Group and tilemap layers are skipped, other colour modes are reported as errors.
+pub fn read<R: Read>(r: &mut R) -> io::Result<Receiver> {
+    let _file_size = r.read_u32::<LE>()?;
+    if r.read_u16::<LE>()? != HEADER_MAGIC {
+        return invalid("not an aseprite file");
+    }
+    let frames = r.read_u16::<LE>()? as usize;
+    let width = r.read_u16::<LE>()? as usize;
+    let height = r.read_u16::<LE>()? as usize;
+    match r.read_u16::<LE>()? {
+        DEPTH_INDEXED => (),
+        DEPTH_RGBA => return unsupported("RGBA colour mode is not supported, convert the sprite to indexed"),
+        DEPTH_GRAYSCALE => return unsupported("grayscale colour mode is not supported, convert the sprite to indexed"),
+        _ => return unsupported("unknown colour mode"),
+    }
+    let _flags = r.read_u32::<LE>()?;
+    let _speed = r.read_u16::<LE>()?;
+    skip(r, 8)?;
+    let transparent = r.read_u8()?;
+    skip(r, 3 + 2 + 2 + 8 + 84)?;
+
+    let mut sprite = Receiver::new("", width, height);
+    sprite.palette.transparent = Some(transparent);
+
+    // aseprite layer index -> our layer, groups and tilemaps have no pages
+    let mut layers: Vec<Option<usize>> = Vec::new();
+    // kept around for linked cels
+    let mut cels: HashMap<(usize, usize), Cel> = HashMap::new();
+    let mut has_palette = false;
+
+    for frame in 0..frames {
+        let _size = r.read_u32::<LE>()?;
+        if r.read_u16::<LE>()? != FRAME_MAGIC {
+            return invalid("broken frame header");
+        }
+        let old_chunks = r.read_u16::<LE>()? as u32;
+        let duration = r.read_u16::<LE>()?;
+        skip(r, 2)?;
+        let new_chunks = r.read_u32::<LE>()?;
+        let chunks = if new_chunks == 0 { old_chunks } else { new_chunks };
+
+        sprite.set_duration(frame, duration as u32);
+
+        for _ in 0..chunks {
+            let size = r.read_u32::<LE>()? as usize;
+            let kind = r.read_u16::<LE>()?;
+            if size < 6 {
+                return invalid("broken chunk header");
+            }
+            let data = read_bytes(r, size - 6)?;
+            let r = &mut &data[..];
+
+            match kind {
+                CHUNK_LAYER => {
+                    let flags = r.read_u16::<LE>()?;
+                    let kind = r.read_u16::<LE>()?;
+                    skip(r, 2 + 2 + 2 + 2 + 1 + 3)?;
+                    let name = read_str(r)?;
+
+                    if kind != LAYER_NORMAL {
+                        layers.push(None);
+                        continue;
+                    }
+
+                    let mut layer = Layer::new(&name);
+                    layer.visible = flags & LAYER_VISIBLE != 0;
+                    layer.lock = flags & LAYER_EDITABLE == 0;
+                    let background = flags & LAYER_BACKGROUND != 0;
+                    for _ in 0..frames {
+                        let mut page = Frame::new(width, height);
+                        if background {
+                            page.transparent = None;
+                        } else {
+                            page.transparent = Some(transparent);
+                            for c in &mut page.page {
+                                *c = transparent;
+                            }
+                        }
+                        layer.push(page);
+                    }
+                    layers.push(Some(sprite.data.len()));
+                    sprite.data.push(layer);
+                }
+                CHUNK_CEL => {
+                    let layer = r.read_u16::<LE>()? as usize;
+                    let x = r.read_i16::<LE>()?;
+                    let y = r.read_i16::<LE>()?;
+                    let _opacity = r.read_u8()?;
+                    let kind = r.read_u16::<LE>()?;
+                    skip(r, 7)?;
+
+                    let layer = match layers.get(layer) {
+                        Some(&Some(layer)) => layer,
+                        _ => continue,
+                    };
+
+                    let cel = match kind {
+                        CEL_RAW | CEL_COMPRESSED => {
+                            let width = r.read_u16::<LE>()? as usize;
+                            let height = r.read_u16::<LE>()? as usize;
+                            let len = match width.checked_mul(height) {
+                                Some(len) => len,
+                                None => return invalid("bad cel size"),
+                            };
+                            if kind == CEL_RAW && len > r.len() {
+                                return invalid("cel is larger than its chunk");
+                            }
+                            let pixels = if kind == CEL_RAW {
+                                read_bytes(r, len)?
+                            } else {
+                                read_bytes(&mut ZlibDecoder::new(r), len)?
+                            };
+                            Cel { x, y, width, height, pixels }
+                        }
+                        CEL_LINKED => {
+                            let src = r.read_u16::<LE>()? as usize;
+                            match cels.get(&(src, layer)) {
+                                Some(cel) => Cel { x, y, .. cel.clone() },
+                                None => return invalid("linked cel points nowhere"),
+                            }
+                        }
+                        _ => continue,
+                    };
+
+                    cel.draw(sprite.page_mut(layer, frame));
+                    cels.insert((frame, layer), cel);
+                }
+                CHUNK_PALETTE => {
+                    has_palette = true;
+                    let size = r.read_u32::<LE>()? as usize;
+                    sprite.palette.size = size.min(256);
+                    let first = r.read_u32::<LE>()? as usize;
+                    let last = r.read_u32::<LE>()? as usize;
+                    skip(r, 8)?;
+                    for i in first..last + 1 {
+                        let flags = r.read_u16::<LE>()?;
+                        let mut c = [0u8; 4];
+                        r.read_exact(&mut c)?;
+                        if flags & 1 != 0 {
+                            read_str(r)?;
+                        }
+                        if i < 256 {
+                            sprite.palette[i as u8] = from_rgba(c[0], c[1], c[2], c[3]);
+                        }
+                    }
+                }
+                CHUNK_OLD_PALETTE | CHUNK_OLD_PALETTE_64 if !has_palette => {
+                    let packets = r.read_u16::<LE>()?;
+                    let mut i = 0usize;
+                    for _ in 0..packets {
+                        i += r.read_u8()? as usize;
+                        let count = match r.read_u8()? {
+                            0 => 256,
+                            n => n as usize,
+                        };
+                        for _ in 0..count {
+                            let mut c = [0u8; 3];
+                            r.read_exact(&mut c)?;
+                            if kind == CHUNK_OLD_PALETTE_64 {
+                                for c in &mut c {
+                                    *c = *c << 2 | *c >> 4;
+                                }
+                            }
+                            if i < 256 {
+                                sprite.palette[i as u8] = from_rgba(c[0], c[1], c[2], 0xFF);
+                            }
+                            i += 1;
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    if sprite.data.is_empty() {
+        sprite.add_layer("Layer 1");
+    }
+
+    Ok(sprite)
+}
+
+fn write_chunk(w: &mut Vec<u8>, kind: u16, data: &[u8]) -> io::Result<()> {
+    w.write_u32::<LE>(data.len() as u32 + 6)?;
+    w.write_u16::<LE>(kind)?;
+    w.write_all(data)
+}
+
+/// Writes the sprite as an indexed Aseprite file, one compressed cel per page.
+pub fn write<W: Write>(w: &mut W, sprite: &Receiver) -> io::Result<()> {
+    let transparent = sprite.palette.transparent
+        .or_else(|| sprite.data.iter()
+            .flat_map(|layer| layer.frames.iter())
+            .filter_map(|page| page.transparent)
+            .next())
+        .unwrap_or(0);
+
+    let frames = sprite.frame_count();
+    let mut body = Vec::new();
+    for frame in 0..frames {
+        let mut chunks = Vec::new();
+        let mut count = 0;
+
+        if frame == 0 {
+            let size = sprite.palette.size.max(1).min(256);
+            let mut data = Vec::new();
+            data.write_u32::<LE>(size as u32)?;
+            data.write_u32::<LE>(0)?;
+            data.write_u32::<LE>(size as u32 - 1)?;
+            data.write_all(&[0; 8])?;
+            for &c in sprite.palette.iter().take(size) {
+                data.write_u16::<LE>(0)?;
+                data.write_all(&to_rgba(c))?;
+            }
+            write_chunk(&mut chunks, CHUNK_PALETTE, &data)?;
+            count += 1;
+
+            for (i, layer) in sprite.data.iter().enumerate() {
+                let background = i == 0 && layer.frames.iter().all(|page| page.transparent.is_none());
+                let mut flags = 0;
+                if layer.visible { flags |= LAYER_VISIBLE }
+                if !layer.lock { flags |= LAYER_EDITABLE }
+                if background { flags |= LAYER_BACKGROUND }
+
+                let mut data = Vec::new();
+                data.write_u16::<LE>(flags)?;
+                data.write_u16::<LE>(LAYER_NORMAL)?;
+                data.write_u16::<LE>(0)?; // child level
+                data.write_all(&[0; 4])?; // default width and height
+                data.write_u16::<LE>(0)?; // normal blend mode
+                data.write_u8(0xFF)?; // opacity
+                data.write_all(&[0; 3])?;
+                write_str(&mut data, &layer.name)?;
+                write_chunk(&mut chunks, CHUNK_LAYER, &data)?;
+                count += 1;
+            }
+        }
+
+        for (i, layer) in sprite.data.iter().enumerate() {
+            let page = match layer.frames.get(frame) {
+                Some(page) => page,
+                None => continue,
+            };
+            if page.transparent == Some(transparent) && page.page.iter().all(|&c| c == transparent) {
+                continue;
+            }
+
+            let mut data = Vec::new();
+            data.write_u16::<LE>(i as u16)?;
+            data.write_i16::<LE>(0)?;
+            data.write_i16::<LE>(0)?;
+            data.write_u8(0xFF)?;
+            data.write_u16::<LE>(CEL_COMPRESSED)?;
+            data.write_all(&[0; 7])?;
+            data.write_u16::<LE>(page.width as u16)?;
+            data.write_u16::<LE>(page.height as u16)?;
+            {
+                let mut e = ZlibEncoder::new(&mut data, Compression::default());
+                e.write_all(&page.page)?;
+                e.finish()?;
+            }
+            write_chunk(&mut chunks, CHUNK_CEL, &data)?;
+            count += 1;
+        }
+
+        body.write_u32::<LE>(chunks.len() as u32 + 16)?;
+        body.write_u16::<LE>(FRAME_MAGIC)?;
+        body.write_u16::<LE>(count.min(0xFFFF) as u16)?;
+        body.write_u16::<LE>(sprite.duration(frame).min(0xFFFF) as u16)?;
+        body.write_all(&[0; 2])?;
+        body.write_u32::<LE>(count)?;
+        body.write_all(&chunks)?;
+    }
+
+    w.write_u32::<LE>(body.len() as u32 + 128)?;
+    w.write_u16::<LE>(HEADER_MAGIC)?;
+    w.write_u16::<LE>(frames as u16)?;
+    w.write_u16::<LE>(sprite.width as u16)?;
+    w.write_u16::<LE>(sprite.height as u16)?;
+    w.write_u16::<LE>(DEPTH_INDEXED)?;
+    w.write_u32::<LE>(1)?; // layer opacity is valid
+    w.write_u16::<LE>(sprite.duration(0).min(0xFFFF) as u16)?; // deprecated speed
+    w.write_all(&[0; 8])?;
+    w.write_u8(transparent)?;
+    w.write_all(&[0; 3])?;
+    w.write_u16::<LE>(if sprite.palette.size >= 256 { 0 } else { sprite.palette.size as u16 })?;
+    w.write_u8(1)?; // pixel ratio
+    w.write_u8(1)?;
+    w.write_all(&[0; 4 + 4 + 84])?; // grid and reserved
+    w.write_all(&body)
+}
+
+#[test]
+fn unsupported_color_mode() {
+    use byteorder::WriteBytesExt;
+
+    let mut buf = Vec::new();
+    buf.write_u32::<LE>(128).unwrap();
+    buf.write_u16::<LE>(HEADER_MAGIC).unwrap();
+    buf.write_u16::<LE>(0).unwrap();
+    buf.write_u16::<LE>(16).unwrap();
+    buf.write_u16::<LE>(16).unwrap();
+    buf.write_u16::<LE>(DEPTH_RGBA).unwrap();
+    buf.resize(128, 0);
+
+    let err = read(&mut &buf[..]).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[cfg(test)]
+fn chunk(kind: u16, data: &[u8]) -> Vec<u8> {
+    use byteorder::WriteBytesExt;
+
+    let mut buf = Vec::new();
+    buf.write_u32::<LE>(data.len() as u32 + 6).unwrap();
+    buf.write_u16::<LE>(kind).unwrap();
+    buf.extend_from_slice(data);
+    buf
+}
+
+#[test]
+fn read_indexed() {
+    use byteorder::WriteBytesExt;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    let layer = |flags: u16, kind: u16, name: &str| {
+        let mut buf = Vec::new();
+        buf.write_u16::<LE>(flags).unwrap();
+        buf.write_u16::<LE>(kind).unwrap();
+        buf.resize(16, 0);
+        buf.write_u16::<LE>(name.len() as u16).unwrap();
+        buf.extend_from_slice(name.as_bytes());
+        chunk(CHUNK_LAYER, &buf)
+    };
+    let cel = |layer: u16, x: i16, y: i16, kind: u16, data: &[u8]| {
+        let mut buf = Vec::new();
+        buf.write_u16::<LE>(layer).unwrap();
+        buf.write_i16::<LE>(x).unwrap();
+        buf.write_i16::<LE>(y).unwrap();
+        buf.write_u8(0xFF).unwrap();
+        buf.write_u16::<LE>(kind).unwrap();
+        buf.resize(16, 0);
+        buf.extend_from_slice(data);
+        chunk(CHUNK_CEL, &buf)
+    };
+    let frame = |duration: u16, chunks: &[Vec<u8>]| {
+        let data: Vec<u8> = chunks.concat();
+        let mut buf = Vec::new();
+        buf.write_u32::<LE>(data.len() as u32 + 16).unwrap();
+        buf.write_u16::<LE>(FRAME_MAGIC).unwrap();
+        buf.write_u16::<LE>(chunks.len() as u16).unwrap();
+        buf.write_u16::<LE>(duration).unwrap();
+        buf.write_u16::<LE>(0).unwrap();
+        buf.write_u32::<LE>(chunks.len() as u32).unwrap();
+        buf.extend_from_slice(&data);
+        buf
+    };
+
+    let mut palette = Vec::new();
+    palette.write_u32::<LE>(3).unwrap();
+    palette.write_u32::<LE>(0).unwrap();
+    palette.write_u32::<LE>(2).unwrap();
+    palette.resize(20, 0);
+    for c in &[[0, 0, 0, 0], [0xFF, 0, 0, 0xFF], [0, 0xFF, 0, 0xFF]] {
+        palette.write_u16::<LE>(0).unwrap();
+        palette.extend_from_slice(c);
+    }
+
+    // 2x2 cel at (1, 0), zlib compressed
+    let mut zlib = Vec::new();
+    zlib.write_u16::<LE>(2).unwrap();
+    zlib.write_u16::<LE>(2).unwrap();
+    {
+        let mut e = ZlibEncoder::new(&mut zlib, Compression::default());
+        e.write_all(&[1, 2, 2, 1]).unwrap();
+        e.finish().unwrap();
+    }
+
+    let mut raw = Vec::new();
+    raw.write_u16::<LE>(1).unwrap();
+    raw.write_u16::<LE>(1).unwrap();
+    raw.push(2);
+
+    let mut linked = Vec::new();
+    linked.write_u16::<LE>(0).unwrap();
+
+    let mut buf = Vec::new();
+    buf.write_u32::<LE>(0).unwrap();
+    buf.write_u16::<LE>(HEADER_MAGIC).unwrap();
+    buf.write_u16::<LE>(2).unwrap();
+    buf.write_u16::<LE>(3).unwrap();
+    buf.write_u16::<LE>(2).unwrap();
+    buf.write_u16::<LE>(DEPTH_INDEXED).unwrap();
+    buf.resize(28, 0);
+    buf.push(0);
+    buf.resize(128, 0);
+
+    buf.extend(frame(120, &[
+        chunk(CHUNK_PALETTE, &palette),
+        layer(LAYER_VISIBLE | LAYER_EDITABLE, LAYER_NORMAL, "body"),
+        layer(LAYER_VISIBLE, 1, "group"),
+        layer(LAYER_EDITABLE, LAYER_NORMAL, "hidden"),
+        cel(0, 1, 0, CEL_COMPRESSED, &zlib),
+        cel(2, 0, 1, CEL_RAW, &raw),
+    ]));
+    buf.extend(frame(80, &[
+        cel(0, 0, 0, CEL_LINKED, &linked),
+    ]));
+
+    let sprite = read(&mut &buf[..]).unwrap();
+    assert_eq!((sprite.width, sprite.height), (3, 2));
+    assert_eq!(sprite.data.len(), 2);
+    assert_eq!(sprite.durations, &[120, 80]);
+    assert_eq!(sprite.palette.size, 3);
+    assert_eq!(sprite.palette[1], 0xFF0000_FF);
+    assert_eq!(sprite.palette.transparent, Some(0));
+
+    assert_eq!(sprite.data[0].name, "body");
+    assert!(sprite.data[0].visible && !sprite.data[0].lock);
+    assert_eq!(sprite.page(0, 0).page, &[0, 1, 2, 0, 2, 1]);
+    assert_eq!(sprite.page(0, 1).page, &[1, 2, 0, 2, 1, 0]);
+    assert_eq!(sprite.page(0, 1).transparent, Some(0));
+
+    assert_eq!(sprite.data[1].name, "hidden");
+    assert!(!sprite.data[1].visible && !sprite.data[1].lock);
+    assert_eq!(sprite.page(1, 0).page, &[0, 0, 0, 2, 0, 0]);
+
+    // cels claiming far more pixels than they carry
+    let mut huge = Vec::new();
+    huge.write_u16::<LE>(0xFFFF).unwrap();
+    huge.write_u16::<LE>(0xFFFF).unwrap();
+    let mut huge_zlib = huge.clone();
+    huge.extend_from_slice(&[1, 2, 2, 1]);
+    huge_zlib.extend_from_slice(&zlib[4..]);
+    for &(kind, data) in &[(CEL_RAW, &huge), (CEL_COMPRESSED, &huge_zlib)] {
+        let mut broken = buf[..128].to_vec();
+        broken.extend(frame(120, &[
+            layer(LAYER_VISIBLE, LAYER_NORMAL, "body"),
+            cel(0, 0, 0, kind, data),
+        ]));
+        assert_eq!(read(&mut &broken[..]).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    }
+}
+
+#[test]
+fn write_round_trip() {
+    let mut sprite = Receiver::new("", 3, 2);
+    sprite.palette.size = 4;
+    sprite.palette[1] = 0xFF0000_FF;
+    sprite.palette[3] = 0x0000FF_80;
+
+    let mut bg = Frame::new(3, 2);
+    bg.transparent = None;
+    for c in &mut bg.page {
+        *c = 3;
+    }
+    sprite.add_layer_page("bg", bg);
+    sprite.add_layer("top");
+    sprite.data[1].frames[0].page[4] = 1;
+    sprite.data[1].lock = true;
+    sprite.data[1].visible = false;
+    let mut page = sprite.page(1, 0).clone();
+    page.page[5] = 1;
+    let bg = sprite.page(0, 0).clone();
+    sprite.data[0].push(bg);
+    sprite.data[1].push(page);
+    sprite.set_duration(1, 250);
+
+    let mut buf = Vec::new();
+    write(&mut buf, &sprite).unwrap();
+
+    let m = read(&mut &buf[..]).unwrap();
+    assert_eq!(m.data.len(), 2);
+    assert_eq!(m.durations, &[100, 250]);
+    assert_eq!(m.palette.size, 4);
+    assert_eq!(m.palette[3], 0x0000FF_80);
+    assert_eq!(m.data[0].name, "bg");
+    assert_eq!(m.data[1].name, "top");
+    assert!(m.data[1].lock && !m.data[1].visible);
+    for layer in 0..2 {
+        for frame in 0..2 {
+            assert_eq!(m.page(layer, frame).page, sprite.page(layer, frame).page);
+            assert_eq!(m.page(layer, frame).transparent, sprite.page(layer, frame).transparent);
+        }
+    }
+}
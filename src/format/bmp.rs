@@ -0,0 +1,221 @@
+// Windows bitmaps with a colour table: 1, 4 and 8 bits per pixel,
+// uncompressed or RLE8. Written as uncompressed 8-bit, bottom-up.
+
+use std::io::{self, Read, Write};
+use byteorder::{LittleEndian as LE, ReadBytesExt, WriteBytesExt};
+
+use draw::{Frame, Palette};
+use tool::Receiver;
+use super::{invalid, unsupported, from_rgba, to_rgba, color_count};
+
+const MAGIC: &[u8; 2] = b"BM";
+const FILE_HEADER: usize = 14;
+const INFO_HEADER: usize = 40;
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+
+/// 72 DPI in pixels per metre.
+const RESOLUTION: i32 = 2835;
+
+pub fn read<R: Read>(r: &mut R) -> io::Result<Receiver> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    if data.len() < FILE_HEADER + INFO_HEADER || &data[..2] != MAGIC {
+        return invalid("not a BMP file");
+    }
+
+    let mut h = &data[2..];
+    let _size = h.read_u32::<LE>()?;
+    let _reserved = h.read_u32::<LE>()?;
+    let offset = h.read_u32::<LE>()? as usize;
+    let header = h.read_u32::<LE>()? as usize;
+    if header < INFO_HEADER {
+        return unsupported("OS/2 bitmaps");
+    }
+    let width = h.read_i32::<LE>()?;
+    let height = h.read_i32::<LE>()?;
+    let _planes = h.read_u16::<LE>()?;
+    let bits = h.read_u16::<LE>()? as usize;
+    let compression = h.read_u32::<LE>()?;
+    let _image_size = h.read_u32::<LE>()?;
+    let _resolution = (h.read_i32::<LE>()?, h.read_i32::<LE>()?);
+    let used = h.read_u32::<LE>()? as usize;
+
+    match bits {
+        1 | 4 | 8 => (),
+        _ => return unsupported("bitmaps without a colour table"),
+    }
+    let (w, h) = match height.checked_abs() {
+        Some(h) if width > 0 && h > 0 => (width as usize, h as usize),
+        _ => return invalid("bad bitmap size"),
+    };
+    // negative heights store the top row first
+    let row = |y: usize| if height < 0 { y } else { h - 1 - y };
+
+    let count = if used == 0 { 1 << bits } else { used.min(256) };
+    let start = FILE_HEADER + header;
+    let table = match data.get(start..start + count * 4) {
+        Some(table) => table,
+        None => return invalid("truncated colour table"),
+    };
+
+    let mut sprite = Receiver::new("", w, h);
+    for (i, c) in table.chunks(4).enumerate() {
+        sprite.palette[i as u8] = from_rgba(c[2], c[1], c[0], 0xFF);
+    }
+    sprite.palette.size = count;
+
+    let pixels = match data.get(offset..) {
+        Some(pixels) => pixels,
+        None => return invalid("pixel data out of the file"),
+    };
+
+    let mut page = Frame::new(w, h);
+    page.transparent = None;
+    match compression {
+        BI_RGB => {
+            let stride = (w * bits + 31) / 32 * 4;
+            if pixels.len() < stride * h {
+                return invalid("truncated bitmap");
+            }
+            let mask = ((1 << bits) - 1) as u8;
+            for (y, line) in pixels.chunks(stride).take(h).enumerate() {
+                let y = row(y);
+                for (x, c) in page.page[y * w..y * w + w].iter_mut().enumerate() {
+                    let bit = x * bits;
+                    let shift = 8 - bits - bit % 8;
+                    *c = (line[bit / 8] >> shift) & mask;
+                }
+            }
+        }
+        BI_RLE8 if bits == 8 => {
+            let (mut x, mut y) = (0, 0);
+            let mut i = 0;
+            while i + 1 < pixels.len() {
+                let (n, c) = (pixels[i] as usize, pixels[i + 1]);
+                i += 2;
+                if n > 0 {
+                    for _ in 0..n {
+                        if x < w && y < h {
+                            page.page[row(y) * w + x] = c;
+                        }
+                        x += 1;
+                    }
+                    continue;
+                }
+                match c {
+                    0 => { x = 0; y += 1; }
+                    1 => break,
+                    2 => {
+                        if i + 1 >= pixels.len() {
+                            return invalid("truncated RLE8 data");
+                        }
+                        x += pixels[i] as usize;
+                        y += pixels[i + 1] as usize;
+                        i += 2;
+                    }
+                    n => {
+                        let n = n as usize;
+                        let literal = match pixels.get(i..i + n) {
+                            Some(literal) => literal,
+                            None => return invalid("truncated RLE8 data"),
+                        };
+                        for &c in literal {
+                            if x < w && y < h {
+                                page.page[row(y) * w + x] = c;
+                            }
+                            x += 1;
+                        }
+                        // literal runs are padded to a whole word
+                        i += (n + 1) & !1;
+                    }
+                }
+            }
+        }
+        _ => return unsupported("compressed bitmaps"),
+    }
+
+    sprite.add_layer_page("load", page);
+    Ok(sprite)
+}
+
+/// Writes the page as an uncompressed 8-bit bitmap.
+pub fn write<W: Write>(w: &mut W, page: &Frame, palette: &Palette<u32>) -> io::Result<()> {
+    let count = color_count(page, palette);
+    let stride = (page.width + 3) & !3;
+    let offset = FILE_HEADER + INFO_HEADER + count * 4;
+    let image_size = stride * page.height;
+
+    w.write_all(MAGIC)?;
+    w.write_u32::<LE>((offset + image_size) as u32)?;
+    w.write_u32::<LE>(0)?;
+    w.write_u32::<LE>(offset as u32)?;
+
+    w.write_u32::<LE>(INFO_HEADER as u32)?;
+    w.write_i32::<LE>(page.width as i32)?;
+    w.write_i32::<LE>(page.height as i32)?;
+    w.write_u16::<LE>(1)?;
+    w.write_u16::<LE>(8)?;
+    w.write_u32::<LE>(BI_RGB)?;
+    w.write_u32::<LE>(image_size as u32)?;
+    w.write_i32::<LE>(RESOLUTION)?;
+    w.write_i32::<LE>(RESOLUTION)?;
+    w.write_u32::<LE>(count as u32)?;
+    w.write_u32::<LE>(0)?;
+
+    for &c in &palette.map[..count] {
+        let [r, g, b, _] = to_rgba(c);
+        w.write_all(&[b, g, r, 0])?;
+    }
+
+    let padding = [0u8; 3];
+    for line in page.page.chunks(page.width).rev() {
+        w.write_all(line)?;
+        w.write_all(&padding[..stride - page.width])?;
+    }
+    Ok(())
+}
+
+#[test]
+fn round_trip() {
+    let mut palette = Palette::new(0x000000_FF, None);
+    palette.size = 4;
+    palette[1] = 0x102030_FF;
+    palette[3] = 0xF0E0D0_FF;
+
+    let mut page = Frame::new(3, 2);
+    page.page.copy_from_slice(&[0, 1, 2, 3, 3, 1]);
+
+    let mut buf = Vec::new();
+    write(&mut buf, &page, &palette).unwrap();
+    assert_eq!(buf.len(), FILE_HEADER + INFO_HEADER + 4 * 4 + 2 * 4);
+
+    let m = read(&mut &buf[..]).unwrap();
+    assert_eq!((m.width, m.height), (3, 2));
+    assert_eq!(m.page(0, 0).page, page.page);
+    assert_eq!(m.page(0, 0).transparent, None);
+    assert_eq!(m.palette.size, 4);
+    assert_eq!(m.palette[3], 0xF0E0D0_FF);
+
+    // a height of i32::MIN has no positive counterpart
+    let at = FILE_HEADER + 8;
+    buf[at..at + 4].copy_from_slice(&[0, 0, 0, 0x80]);
+    assert!(read(&mut &buf[..]).is_err());
+}
+
+#[test]
+fn read_rle8() {
+    let mut palette = Palette::new(0x000000_FF, None);
+    palette.size = 4;
+    let mut buf = Vec::new();
+    write(&mut buf, &Frame::new(4, 2), &palette).unwrap();
+
+    // the bottom row is a run of 1s, the top one 2 then a padded literal 3, 2, 0
+    buf.truncate(FILE_HEADER + INFO_HEADER + 4 * 4);
+    buf.extend_from_slice(&[4, 1, 0, 0, 1, 2, 0, 3, 3, 2, 0, 0, 0, 1]);
+    buf[30] = BI_RLE8 as u8;
+
+    let m = read(&mut &buf[..]).unwrap();
+    assert_eq!(m.page(0, 0).page, &[2, 3, 2, 0, 1, 1, 1, 1]);
+}
@@ -0,0 +1,363 @@
+use std::io::{self, Read, Write};
+use std::borrow::Cow;
+
+use gif::{self, SetParameter, DisposalMethod, Repeat};
+
+use math::Rect;
+use draw::Frame;
+use tool::{Receiver, Layer};
+use super::{Source, nearest, from_rgba, to_rgba};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Loop {
+    Once,
+    Forever,
+    Times(u16),
+}
+
+impl Loop {
+    /// Plays the animation `n` times in total, 0 loops forever.
+    pub fn plays(n: u16) -> Self {
+        match n {
+            0 => Loop::Forever,
+            1 => Loop::Once,
+            n => Loop::Times(n - 1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    pub source: Source,
+    pub repeat: Loop,
+    /// Store only the rectangle that changed since the previous frame.
+    pub crop: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            source: Source::Composite,
+            repeat: Loop::Forever,
+            crop: true,
+        }
+    }
+}
+
+fn decoding(err: gif::DecodingError) -> io::Error {
+    match err {
+        gif::DecodingError::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+    }
+}
+
+/// Maps a local colour table onto the sprite palette.
+/// Unknown colours take free slots and fall back to the nearest one.
+fn merge_palette(colors: &mut Vec<[u8; 3]>, local: &[u8]) -> [u8; 256] {
+    let mut table = [0u8; 256];
+    for (i, c) in local.chunks(3).take(256).enumerate() {
+        let c = [c[0], c[1], c[2]];
+        table[i] = if colors.get(i) == Some(&c) {
+            i as u8
+        } else if let Some(idx) = colors.iter().position(|&p| p == c) {
+            idx as u8
+        } else if colors.len() < 256 {
+            colors.push(c);
+            (colors.len() - 1) as u8
+        } else {
+            let rgb = |p: &[u8; 3]| from_rgba(p[0], p[1], p[2], 0xFF);
+            let full: Vec<u32> = colors.iter().map(rgb).collect();
+            nearest(&full, rgb(&c)) as u8
+        };
+    }
+    table
+}
+
+/// Reads every frame of a GIF into a single layer keeping indices and palette order.
+///
+/// Frames are composited onto the full canvas honoring their offsets and dispose modes,
+/// delays are kept as frame durations.
+pub fn read<R: Read>(r: R) -> io::Result<Receiver> {
+    let mut decoder = gif::Decoder::new(r);
+    decoder.set(gif::ColorOutput::Indexed);
+    let mut reader = decoder.read_info().map_err(decoding)?;
+
+    let (w, h) = (reader.width() as usize, reader.height() as usize);
+    let bg = reader.bg_color().map(|c| c as u8);
+
+    let mut colors: Vec<[u8; 3]> = reader.global_palette()
+        .map(|pal| pal.chunks(3).take(256).map(|c| [c[0], c[1], c[2]]).collect())
+        .unwrap_or_default();
+
+    let mut frames = Vec::new();
+    while let Some(frame) = reader.read_next_frame().map_err(decoding)? {
+        frames.push(frame.clone());
+    }
+
+    let mut identity = [0u8; 256];
+    for (i, c) in identity.iter_mut().enumerate() {
+        *c = i as u8;
+    }
+
+    let tables: Vec<_> = frames.iter()
+        .map(|frame| match frame.palette {
+            Some(ref local) => merge_palette(&mut colors, local),
+            None => identity,
+        })
+        .collect();
+
+    let transparent = frames.iter().zip(&tables)
+        .filter_map(|(frame, table)| frame.transparent.map(|c| table[c as usize]))
+        .next();
+    let fill = transparent.or(bg).unwrap_or(0);
+
+    let mut sprite = Receiver::new("", w, h);
+    for (i, c) in colors.iter().enumerate() {
+        sprite.palette[i as u8] = from_rgba(c[0], c[1], c[2], 0xFF);
+    }
+    sprite.palette.size = colors.len().max(1);
+
+    let mut layer = Layer::new("load");
+    let mut canvas = vec![fill; w * h];
+    for (i, (frame, table)) in frames.iter().zip(&tables).enumerate() {
+        let previous = if frame.dispose == DisposalMethod::Previous {
+            Some(canvas.clone())
+        } else {
+            None
+        };
+
+        // frames may hang past the logical screen, only the overlap is drawn
+        let (left, top) = (w.min(frame.left as usize), h.min(frame.top as usize));
+        let right = w.min(left + frame.width as usize);
+        let bottom = h.min(top + frame.height as usize);
+        let stride = frame.width as usize;
+
+        for y in top..bottom {
+            let line = &frame.buffer[(y - top) * stride..];
+            for x in left..right {
+                let c = line[x - left];
+                if Some(c) != frame.transparent {
+                    canvas[x + y * w] = table[c as usize];
+                }
+            }
+        }
+
+        let mut page = Frame::new(w, h);
+        page.page.copy_from_slice(&canvas);
+        page.transparent = transparent;
+        layer.push(page);
+        sprite.set_duration(i, frame.delay as u32 * 10);
+
+        match frame.dispose {
+            DisposalMethod::Background => {
+                for y in top..bottom {
+                    for c in &mut canvas[left + y * w..right + y * w] {
+                        *c = fill;
+                    }
+                }
+            }
+            DisposalMethod::Previous => canvas = previous.unwrap(),
+            _ => (),
+        }
+    }
+
+    if layer.frames.is_empty() {
+        let mut page = Frame::new(w, h);
+        page.transparent = transparent;
+        layer.push(page);
+    }
+
+    sprite.data.push(layer);
+    Ok(sprite)
+}
+
+/// Bounding box of the pixels that differ between two pages.
+fn diff_rect(a: &[u8], b: &[u8], width: usize) -> Option<Rect<usize>> {
+    let mut r: Option<Rect<usize>> = None;
+    for (i, _) in a.iter().zip(b).enumerate().filter(|&(_, (a, b))| a != b) {
+        let (x, y) = (i % width, i / width);
+        r = Some(match r {
+            Some(r) => Rect::from_coords(r.min.x.min(x), r.min.y.min(y), r.max.x.max(x + 1), r.max.y.max(y + 1)),
+            None => Rect::from_coords(x, y, x + 1, y + 1),
+        });
+    }
+    r
+}
+
+/// Writes every frame of the sprite using its own palette, without any re-quantization.
+pub fn write<W: Write>(w: W, sprite: &Receiver, opts: &Options) -> io::Result<()> {
+    let (width, height) = (sprite.width, sprite.height);
+
+    let mut palette = Vec::with_capacity(256 * 3);
+    for &c in sprite.palette.iter().take(256) {
+        palette.extend_from_slice(&to_rgba(c)[..3]);
+    }
+
+    let mut encoder = gif::Encoder::new(w, width as u16, height as u16, &palette)?;
+    match opts.repeat {
+        Loop::Once => (),
+        Loop::Forever => encoder.set(Repeat::Infinite)?,
+        Loop::Times(n) => encoder.set(Repeat::Finite(n))?,
+    }
+
+    let count = sprite.frame_count();
+    let pages: Vec<_> = (0..count).map(|i| opts.source.page(sprite, i)).collect();
+
+    // what the decoder shows after the previous frame was disposed
+    let mut screen: Option<Vec<u8>> = None;
+    for (i, page) in pages.iter().enumerate() {
+        let t = page.transparent;
+
+        // pixels turning transparent can't be drawn over the old ones,
+        // so the whole frame goes out and is cleared afterwards
+        let clear = t.is_some() && pages.get(i + 1).map_or(false, |next| {
+            next.page.iter().zip(&page.page).any(|(&n, &c)| Some(n) == t && Some(c) != t)
+        });
+
+        let full = Rect::from_coords(0, 0, width, height);
+        let rect = match screen {
+            Some(ref screen) if opts.crop && !clear =>
+                diff_rect(screen, &page.page, width).unwrap_or(Rect::from_coords(0, 0, 1, 1)),
+            _ => full,
+        };
+
+        let mut buffer = Vec::with_capacity(rect.dx() * rect.dy());
+        for y in rect.min.y..rect.max.y {
+            for x in rect.min.x..rect.max.x {
+                let c = page.page[x + y * width];
+                let same = screen.as_ref().map_or(false, |screen| screen[x + y * width] == c);
+                buffer.push(match t {
+                    Some(t) if same => t,
+                    _ => c,
+                });
+            }
+        }
+
+        let mut frame = gif::Frame::default();
+        frame.left = rect.min.x as u16;
+        frame.top = rect.min.y as u16;
+        frame.width = rect.dx() as u16;
+        frame.height = rect.dy() as u16;
+        frame.delay = ((sprite.duration(i) + 5) / 10) as u16;
+        frame.transparent = t;
+        frame.dispose = if clear { DisposalMethod::Background } else { DisposalMethod::Keep };
+        frame.buffer = Cow::Owned(buffer);
+        encoder.write_frame(&frame)?;
+
+        screen = Some(match (clear, t) {
+            (true, Some(t)) => vec![t; width * height],
+            _ => page.page.clone(),
+        });
+    }
+    Ok(())
+}
+
+#[test]
+fn read_animation() {
+    use gif::Encoder;
+
+    let mut buf = Vec::new();
+    {
+        let pal = [0, 0, 0, 0xFF, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF];
+        let mut encoder = Encoder::new(&mut buf, 3, 2, &pal).unwrap();
+
+        let mut frame = gif::Frame::default();
+        frame.width = 3;
+        frame.height = 2;
+        frame.delay = 5;
+        frame.transparent = Some(0);
+        frame.buffer = vec![1, 1, 1, 0, 0, 0].into();
+        encoder.write_frame(&frame).unwrap();
+
+        // 1x1 at (1, 1) with its own colour table, disposed to background
+        let mut frame = gif::Frame::default();
+        frame.left = 1;
+        frame.top = 1;
+        frame.width = 1;
+        frame.height = 1;
+        frame.delay = 7;
+        frame.dispose = DisposalMethod::Background;
+        frame.palette = Some(vec![0x12, 0x34, 0x56, 0, 0, 0xFF]);
+        frame.buffer = vec![0].into();
+        encoder.write_frame(&frame).unwrap();
+
+        let mut frame = gif::Frame::default();
+        frame.left = 2;
+        frame.width = 1;
+        frame.height = 1;
+        frame.transparent = Some(0);
+        frame.buffer = vec![3].into();
+        encoder.write_frame(&frame).unwrap();
+    }
+
+    let sprite = read(&buf[..]).unwrap();
+    assert_eq!((sprite.width, sprite.height), (3, 2));
+    assert_eq!(sprite.data.len(), 1);
+    assert_eq!(sprite.frame_count(), 3);
+    assert_eq!(sprite.durations, &[50, 70, 0]);
+
+    // the local colour took the first free slot
+    assert_eq!(sprite.palette[4], 0x123456_FF);
+    assert_eq!(sprite.palette.size, 5);
+    assert_eq!(sprite.page(0, 0).page, &[1, 1, 1, 0, 0, 0]);
+    assert_eq!(sprite.page(0, 1).page, &[1, 1, 1, 0, 4, 0]);
+    assert_eq!(sprite.page(0, 2).page, &[1, 1, 3, 0, 0, 0]);
+    assert_eq!(sprite.page(0, 2).transparent, Some(0));
+}
+
+#[test]
+fn write_animation() {
+    let mut sprite = Receiver::new("", 4, 3);
+    sprite.palette[1] = 0xFF0000_FF;
+    sprite.palette[2] = 0x00FF00_FF;
+    sprite.add_layer("a");
+    sprite.data[0].frames[0].page[5] = 1;
+    for i in 1..4 {
+        let mut page = sprite.page(0, i - 1).clone();
+        page.page[5 + i] = 2;
+        sprite.data[0].push(page);
+        sprite.set_duration(i, 40 * i as u32);
+    }
+    // pixels going back to transparent
+    sprite.data[0].frames[3].page[5] = 0;
+
+    for &crop in &[false, true] {
+        let opts = Options { crop, .. Options::default() };
+        let mut buf = Vec::new();
+        write(&mut buf, &sprite, &opts).unwrap();
+
+        let m = read(&buf[..]).unwrap();
+        assert_eq!(m.frame_count(), 4);
+        assert_eq!(m.durations, &[100, 40, 80, 120]);
+        for i in 0..4 {
+            assert_eq!(m.page(0, i).page, sprite.page(0, i).page);
+            assert_eq!(m.page(0, i).transparent, Some(0));
+        }
+        assert_eq!(m.palette[2], 0x00FF00_FF);
+    }
+}
+
+#[test]
+fn read_frame_off_screen() {
+    use gif::Encoder;
+
+    let mut buf = Vec::new();
+    {
+        let pal = [0, 0, 0, 0xFF, 0xFF, 0xFF];
+        let mut encoder = Encoder::new(&mut buf, 2, 2, &pal).unwrap();
+
+        let mut frame = gif::Frame::default();
+        frame.left = 5;
+        frame.top = 1;
+        frame.width = 2;
+        frame.height = 2;
+        frame.dispose = DisposalMethod::Background;
+        frame.buffer = vec![1, 1, 1, 1].into();
+        encoder.write_frame(&frame).unwrap();
+    }
+
+    let sprite = read(&buf[..]).unwrap();
+    assert_eq!(sprite.frame_count(), 1);
+    assert_eq!(sprite.page(0, 0).page, &[0, 0, 0, 0]);
+    assert_eq!(sprite.palette.size, 2);
+}
@@ -0,0 +1,391 @@
+// Electronic Arts IFF pictures as written by Deluxe Paint: planar `ILBM`
+// and chunky `PBM `, uncompressed or ByteRun1, with `CRNG` colour cycling
+// ranges. Everything is big-endian and chunks are padded to an even size.
+
+use std::io::{self, Read, Write};
+use byteorder::{BigEndian as BE, ByteOrder, WriteBytesExt};
+
+use draw::{Frame, Palette};
+use tool::{Receiver, Cycle};
+use super::{invalid, unsupported, from_rgba, to_rgba, color_count};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Bitplanes, the Amiga flavour.
+    Ilbm,
+    /// One byte per pixel, what Deluxe Paint on the PC writes.
+    Pbm,
+}
+
+impl Kind {
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "iff" | "ilbm" => Some(Kind::Ilbm),
+            "lbm" | "bbm" => Some(Kind::Pbm),
+            _ => None,
+        }
+    }
+}
+
+const MASK_NONE: u8 = 0;
+const MASK_PLANE: u8 = 1;
+const MASK_TRANSPARENT: u8 = 2;
+
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_BYTERUN1: u8 = 1;
+
+const CAMG_EHB: u32 = 0x0080;
+const CAMG_HAM: u32 = 0x0800;
+
+const CRNG_ACTIVE: u16 = 1;
+const CRNG_REVERSE: u16 = 2;
+
+struct Header {
+    width: usize,
+    height: usize,
+    planes: usize,
+    masking: u8,
+    compression: u8,
+    transparent: u8,
+}
+
+fn unpack(src: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    while out.len() < len && i < src.len() {
+        let n = src[i] as i8;
+        i += 1;
+        if n >= 0 {
+            let n = n as usize + 1;
+            match src.get(i..i + n) {
+                Some(literal) => out.extend_from_slice(literal),
+                None => return invalid("truncated ByteRun1 data"),
+            }
+            i += n;
+        } else if n != -128 {
+            let c = match src.get(i) {
+                Some(&c) => c,
+                None => return invalid("truncated ByteRun1 data"),
+            };
+            i += 1;
+            for _ in 0..1 - n as isize {
+                out.push(c);
+            }
+        }
+    }
+    if out.len() < len {
+        return invalid("truncated image");
+    }
+    out.truncate(len);
+    Ok(out)
+}
+
+fn pack(src: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < src.len() {
+        let c = src[i];
+        let mut run = 1;
+        while i + run < src.len() && run < 128 && src[i + run] == c {
+            run += 1;
+        }
+        if run >= 3 {
+            out.push((257 - run) as u8);
+            out.push(c);
+            i += run;
+            continue;
+        }
+
+        // literals up to the next run worth packing
+        let start = i;
+        i += 1;
+        while i < src.len() && i - start < 128 &&
+            !(i + 2 < src.len() && src[i] == src[i + 1] && src[i] == src[i + 2])
+        {
+            i += 1;
+        }
+        out.push((i - start - 1) as u8);
+        out.extend_from_slice(&src[start..i]);
+    }
+}
+
+pub fn read<R: Read>(r: &mut R) -> io::Result<Receiver> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    if data.len() < 12 || &data[..4] != b"FORM" {
+        return invalid("not an IFF file");
+    }
+    let kind = match &data[8..12] {
+        b"ILBM" => Kind::Ilbm,
+        b"PBM " => Kind::Pbm,
+        _ => return unsupported("IFF files other than ILBM or PBM"),
+    };
+    let end = (BE::read_u32(&data[4..]) as usize + 8).min(data.len());
+
+    let mut header = None;
+    let mut colors = Vec::new();
+    let mut camg = 0;
+    let mut cycles = Vec::new();
+    let mut body = None;
+
+    let mut i = 12;
+    while i + 8 <= end {
+        let len = BE::read_u32(&data[i + 4..]) as usize;
+        let chunk = match data.get(i + 8..i + 8 + len) {
+            Some(chunk) => chunk,
+            None => return invalid("truncated chunk"),
+        };
+        match &data[i..i + 4] {
+            b"BMHD" if len >= 20 => header = Some(Header {
+                width: BE::read_u16(&chunk[0..]) as usize,
+                height: BE::read_u16(&chunk[2..]) as usize,
+                planes: chunk[8] as usize,
+                masking: chunk[9],
+                compression: chunk[10],
+                transparent: BE::read_u16(&chunk[12..]) as u8,
+            }),
+            b"CMAP" => colors = chunk.chunks(3)
+                .filter(|c| c.len() == 3)
+                .take(256)
+                .map(|c| from_rgba(c[0], c[1], c[2], 0xFF))
+                .collect(),
+            b"CAMG" if len >= 4 => camg = BE::read_u32(chunk),
+            b"CRNG" if len >= 8 => {
+                let flags = BE::read_u16(&chunk[4..]);
+                cycles.push(Cycle {
+                    rate: BE::read_u16(&chunk[2..]),
+                    active: flags & CRNG_ACTIVE != 0,
+                    reverse: flags & CRNG_REVERSE != 0,
+                    low: chunk[6],
+                    high: chunk[7],
+                });
+            }
+            b"BODY" => body = Some(chunk),
+            _ => (),
+        }
+        i += 8 + len + (len & 1);
+    }
+
+    let header = match header {
+        Some(header) => header,
+        None => return invalid("missing BMHD chunk"),
+    };
+    let body = match body {
+        Some(body) => body,
+        None => return invalid("missing BODY chunk"),
+    };
+    let (w, h) = (header.width, header.height);
+    if w == 0 || h == 0 {
+        return invalid("bad picture size");
+    }
+
+    if camg & CAMG_HAM != 0 {
+        return unsupported("HAM pictures");
+    }
+    match (kind, header.planes) {
+        (Kind::Ilbm, p) if p >= 1 && p <= 8 => (),
+        (Kind::Pbm, 8) => (),
+        _ => return unsupported("pictures deeper than 8 bits"),
+    }
+
+    let (row, lines) = match kind {
+        Kind::Ilbm => ((w + 15) / 16 * 2, header.planes + (header.masking == MASK_PLANE) as usize),
+        Kind::Pbm => ((w + 1) & !1, 1),
+    };
+    let line = row * lines;
+    let raw = match header.compression {
+        COMPRESSION_NONE if body.len() >= line * h => body[..line * h].to_vec(),
+        COMPRESSION_NONE => return invalid("truncated image"),
+        COMPRESSION_BYTERUN1 => unpack(body, line * h)?,
+        _ => return unsupported("unknown IFF compression"),
+    };
+
+    let mut page = Frame::new(w, h);
+    page.transparent = None;
+    for (src, dst) in raw.chunks(line).zip(page.page.chunks_mut(w)) {
+        for (x, c) in dst.iter_mut().enumerate() {
+            *c = match kind {
+                Kind::Pbm => src[x],
+                Kind::Ilbm => (0..header.planes).fold(0, |c, p| {
+                    c | ((src[p * row + x / 8] >> (7 - x % 8)) & 1) << p
+                }),
+            };
+        }
+    }
+
+    // without a CMAP the planes are read as a grey ramp
+    if colors.is_empty() {
+        let count = 1usize << header.planes.min(8);
+        colors = (0..count)
+            .map(|i| (i * 255 / (count - 1).max(1)) as u8)
+            .map(|v| from_rgba(v, v, v, 0xFF))
+            .collect();
+    }
+
+    let mut sprite = Receiver::new("", w, h);
+    for (i, &c) in colors.iter().enumerate() {
+        sprite.palette[i as u8] = c;
+    }
+    sprite.palette.size = colors.len();
+    // extra half-bright: the upper 32 colours are the lower ones at half intensity
+    if camg & CAMG_EHB != 0 && header.planes == 6 {
+        for i in 0..32 {
+            let [r, g, b, a] = to_rgba(sprite.palette[i]);
+            sprite.palette[i + 32] = from_rgba(r / 2, g / 2, b / 2, a);
+        }
+        sprite.palette.size = 64;
+    }
+
+    if header.masking == MASK_TRANSPARENT {
+        page.transparent = Some(header.transparent);
+        sprite.palette.transparent = Some(header.transparent);
+    }
+
+    sprite.cycles = cycles;
+    sprite.add_layer_page("load", page);
+    Ok(sprite)
+}
+
+fn chunk<W: Write>(w: &mut W, id: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(id)?;
+    w.write_u32::<BE>(data.len() as u32)?;
+    w.write_all(data)?;
+    if data.len() & 1 != 0 {
+        w.write_u8(0)?;
+    }
+    Ok(())
+}
+
+/// Writes the page ByteRun1 packed, with a colour cycling chunk for every range.
+pub fn write<W: Write>(w: &mut W, page: &Frame, palette: &Palette<u32>, cycles: &[Cycle], kind: Kind) -> io::Result<()> {
+    let (width, height) = (page.width, page.height);
+    let planes = match kind {
+        Kind::Ilbm => (1..9).find(|&p| 1 << p >= color_count(page, palette)).unwrap_or(8),
+        Kind::Pbm => 8,
+    };
+
+    let mut form = Vec::new();
+    form.extend_from_slice(match kind {
+        Kind::Ilbm => b"ILBM",
+        Kind::Pbm => b"PBM ",
+    });
+
+    let mut bmhd = Vec::with_capacity(20);
+    bmhd.write_u16::<BE>(width as u16)?;
+    bmhd.write_u16::<BE>(height as u16)?;
+    bmhd.write_i16::<BE>(0)?;
+    bmhd.write_i16::<BE>(0)?;
+    bmhd.write_u8(planes as u8)?;
+    bmhd.write_u8(if page.transparent.is_some() { MASK_TRANSPARENT } else { MASK_NONE })?;
+    bmhd.write_u8(COMPRESSION_BYTERUN1)?;
+    bmhd.write_u8(0)?;
+    bmhd.write_u16::<BE>(page.transparent.unwrap_or(0) as u16)?;
+    bmhd.write_u8(1)?;
+    bmhd.write_u8(1)?;
+    bmhd.write_i16::<BE>(width as i16)?;
+    bmhd.write_i16::<BE>(height as i16)?;
+    chunk(&mut form, b"BMHD", &bmhd)?;
+
+    let cmap: Vec<u8> = palette.map[..1 << planes].iter()
+        .flat_map(|&c| to_rgba(c)[..3].to_vec())
+        .collect();
+    chunk(&mut form, b"CMAP", &cmap)?;
+
+    for cycle in cycles {
+        let mut crng = Vec::with_capacity(8);
+        let mut flags = 0;
+        if cycle.active { flags |= CRNG_ACTIVE }
+        if cycle.reverse { flags |= CRNG_REVERSE }
+        crng.write_u16::<BE>(0)?;
+        crng.write_u16::<BE>(cycle.rate)?;
+        crng.write_u16::<BE>(flags)?;
+        crng.write_u8(cycle.low)?;
+        crng.write_u8(cycle.high)?;
+        chunk(&mut form, b"CRNG", &crng)?;
+    }
+
+    let mut body = Vec::new();
+    match kind {
+        Kind::Ilbm => {
+            let row = (width + 15) / 16 * 2;
+            let mut plane = vec![0u8; row];
+            for src in page.page.chunks(width) {
+                for p in 0..planes {
+                    for b in &mut plane { *b = 0 }
+                    for (x, &c) in src.iter().enumerate() {
+                        plane[x / 8] |= ((c >> p) & 1) << (7 - x % 8);
+                    }
+                    pack(&plane, &mut body);
+                }
+            }
+        }
+        Kind::Pbm => {
+            let mut line = vec![0u8; (width + 1) & !1];
+            for src in page.page.chunks(width) {
+                line[..width].copy_from_slice(src);
+                pack(&line, &mut body);
+            }
+        }
+    }
+    chunk(&mut form, b"BODY", &body)?;
+
+    chunk(w, b"FORM", &form)
+}
+
+#[test]
+fn byterun1() {
+    let src = [1, 2, 3, 3, 3, 3, 4, 4, 5];
+    let mut packed = Vec::new();
+    pack(&src, &mut packed);
+    assert_eq!(packed, &[1, 1, 2, 0xFD, 3, 2, 4, 4, 5]);
+    assert_eq!(unpack(&packed, src.len()).unwrap(), &src);
+}
+
+#[test]
+fn round_trip() {
+    let mut palette = Palette::new(0x000000_FF, None);
+    palette.size = 5;
+    palette[4] = 0x8040C0_FF;
+
+    let mut page = Frame::new(19, 2);
+    for (i, c) in page.page.iter_mut().enumerate() {
+        *c = (i % 5) as u8;
+    }
+    page.transparent = Some(4);
+    let cycles = [Cycle { low: 1, high: 3, rate: 16384, active: true, reverse: true }];
+
+    for &kind in &[Kind::Ilbm, Kind::Pbm] {
+        let mut buf = Vec::new();
+        write(&mut buf, &page, &palette, &cycles, kind).unwrap();
+        let m = read(&mut &buf[..]).unwrap();
+        assert_eq!((m.width, m.height), (19, 2), "{:?}", kind);
+        assert_eq!(m.page(0, 0).page, page.page, "{:?}", kind);
+        assert_eq!(m.page(0, 0).transparent, Some(4), "{:?}", kind);
+        assert_eq!(m.palette[4], 0x8040C0_FF, "{:?}", kind);
+        assert_eq!(m.cycles, &cycles, "{:?}", kind);
+
+        // zero BMHD width
+        buf[20] = 0;
+        buf[21] = 0;
+        assert_eq!(read(&mut &buf[..]).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    }
+}
+
+#[test]
+fn read_without_cmap() {
+    let mut palette = Palette::new(0x000000_FF, None);
+    palette.size = 4;
+    let mut page = Frame::new(4, 1);
+    page.page.copy_from_slice(&[0, 1, 2, 3]);
+
+    let mut buf = Vec::new();
+    write(&mut buf, &page, &palette, &[], Kind::Ilbm).unwrap();
+    let at = buf.windows(4).position(|id| id == b"CMAP").unwrap();
+    let len = BE::read_u32(&buf[at + 4..]) as usize;
+    buf.drain(at..at + 8 + len + (len & 1));
+
+    let m = read(&mut &buf[..]).unwrap();
+    assert_eq!(m.page(0, 0).page, page.page);
+    assert_eq!(m.palette.size, 4);
+    assert_eq!(m.palette[1], 0x555555_FF);
+    assert_eq!(m.palette[3], 0xFFFFFF_FF);
+}
@@ -0,0 +1,223 @@
+#![allow(dead_code)]
+
+pub mod native;
+pub mod png;
+pub mod gif;
+pub mod aseprite;
+pub mod sheet;
+pub mod preview;
+pub mod tiled;
+pub mod palette;
+pub mod quantize;
+pub mod bmp;
+pub mod pcx;
+pub mod ilbm;
+
+use std::{io, fmt};
+use std::io::Read;
+
+use draw::{Frame, Palette};
+use tool::{Receiver, Layer};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// Unknown file extension or a variant of the format we can't handle.
+    Unsupported(String),
+    Decode(String),
+    /// The image needs more colours than fit in a palette.
+    TooManyColors(usize),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        // the decoders report broken data through `io::Error`
+        match err.kind() {
+            io::ErrorKind::InvalidData => Error::Decode(err.to_string()),
+            io::ErrorKind::InvalidInput => Error::Unsupported(err.to_string()),
+            _ => Error::Io(err),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "{}", err),
+            Error::Unsupported(ref what) => write!(f, "unsupported format: {}", what),
+            Error::Decode(ref msg) => write!(f, "broken file: {}", msg),
+            Error::TooManyColors(count) => write!(f, "too many colours: {}, at most 256 fit", count),
+        }
+    }
+}
+
+impl ::std::error::Error for Error {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Composite,
+    Layer(usize),
+}
+
+impl Source {
+    pub fn page(self, sprite: &Receiver, frame: usize) -> Frame {
+        match self {
+            Source::Composite => sprite.composite(frame),
+            Source::Layer(layer) => sprite.data[layer].frames.get(frame)
+                .cloned()
+                .unwrap_or_else(|| Frame::new(sprite.width, sprite.height)),
+        }
+    }
+}
+
+pub fn to_rgba(c: u32) -> [u8; 4] {
+    [(c >> 24) as u8, (c >> 16) as u8, (c >> 8) as u8, c as u8]
+}
+
+pub fn from_rgba(r: u8, g: u8, b: u8, a: u8) -> u32 {
+    (r as u32) << 24 | (g as u32) << 16 | (b as u32) << 8 | a as u32
+}
+
+/// Broken or truncated data.
+pub fn invalid<T>(msg: &str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+/// Valid data in a variant we can't handle.
+pub fn unsupported<T>(msg: &str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// Index of the colour closest to `c` in RGB, alpha is ignored.
+pub fn nearest(colors: &[u32], c: u32) -> usize {
+    let [r, g, b, _] = to_rgba(c);
+    let dist = |p: u32| -> i32 {
+        let [pr, pg, pb, _] = to_rgba(p);
+        let r = pr as i32 - r as i32;
+        let g = pg as i32 - g as i32;
+        let b = pb as i32 - b as i32;
+        r * r + g * g + b * b
+    };
+    (0..colors.len()).min_by_key(|&i| dist(colors[i])).unwrap_or(0)
+}
+
+/// Reads `len` bytes, growing the buffer only as far as the data goes
+/// so a broken length can't ask for gigabytes up front.
+pub fn read_bytes<R: Read>(r: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    r.by_ref().take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return invalid("truncated data");
+    }
+    Ok(buf)
+}
+
+/// Palette entries a file needs so every pixel of the page has a colour.
+pub fn color_count(page: &Frame, palette: &Palette<u32>) -> usize {
+    let used = page.page.iter().cloned().max().map_or(0, |c| c as usize + 1);
+    palette.size.max(used).max(1)
+}
+
+/// Nearest-neighbour upscale of a page.
+pub fn scale_page(page: &Frame, factor: usize) -> Frame {
+    let factor = factor.max(1);
+    let mut dst = Frame::new(page.width * factor, page.height * factor);
+    dst.transparent = page.transparent;
+    for (y, line) in dst.page.chunks_mut(page.width * factor).enumerate() {
+        let src = &page.page[(y / factor) * page.width..];
+        for (x, c) in line.iter_mut().enumerate() {
+            *c = src[x / factor];
+        }
+    }
+    dst
+}
+
+/// Nearest-neighbour upscale of every page in the sprite.
+pub fn scale(sprite: &Receiver, factor: usize) -> Receiver {
+    let factor = factor.max(1);
+    let mut m = Receiver::new(&sprite.name, sprite.width * factor, sprite.height * factor);
+    m.palette.map = sprite.palette.map;
+    m.palette.size = sprite.palette.size;
+    m.palette.transparent = sprite.palette.transparent;
+    m.cycles = sprite.cycles.clone();
+    m.tiles = sprite.tiles.clone();
+    m.durations = sprite.durations.clone();
+    for layer in &sprite.data {
+        let mut dst = Layer::new(&layer.name);
+        dst.visible = layer.visible;
+        dst.lock = layer.lock;
+        for page in &layer.frames {
+            dst.push(scale_page(page, factor));
+        }
+        m.data.push(dst);
+    }
+    m
+}
+
+/// The image as a layer for the sprite, remapped to the nearest colours of the
+/// sprite palette and cut to the sprite size. The layer gets as many frames
+/// as the sprite has, extra frames of the image are dropped.
+pub fn to_layer(sprite: &Receiver, image: &Receiver, name: &str) -> Layer {
+    let mut table = [0u8; 256];
+    {
+        let colors = &sprite.palette.map[..sprite.palette.size.max(1)];
+        for (i, c) in table.iter_mut().enumerate() {
+            *c = nearest(colors, image.palette[i as u8]) as u8;
+        }
+    }
+
+    let transparent = sprite.palette.transparent.unwrap_or(0);
+    let mut layer = Layer::new(name);
+    for frame in 0..sprite.frame_count().max(1) {
+        let mut page = Frame::new(sprite.width, sprite.height);
+        page.transparent = Some(transparent);
+        for c in &mut page.page {
+            *c = transparent;
+        }
+        if frame < image.frame_count() {
+            let src = image.composite(frame);
+            for y in 0..src.height.min(sprite.height) {
+                for x in 0..src.width.min(sprite.width) {
+                    let c = src.page[x + y * src.width];
+                    if Some(c) != src.transparent {
+                        page.page[x + y * sprite.width] = table[c as usize];
+                    }
+                }
+            }
+        }
+        layer.push(page);
+    }
+    layer
+}
+
+#[test]
+fn scale_pages() {
+    let mut page = Frame::new(2, 1);
+    page.page.copy_from_slice(&[1, 2]);
+    let m = scale_page(&page, 3);
+    assert_eq!((m.width, m.height), (6, 3));
+    assert_eq!(&m.page[..6], &[1, 1, 1, 2, 2, 2]);
+    assert_eq!(&m.page[12..], &[1, 1, 1, 2, 2, 2]);
+}
+
+#[test]
+fn add_remapped_layer() {
+    let mut sprite = Receiver::new("", 3, 1);
+    sprite.palette.size = 3;
+    sprite.palette[1] = 0xFF0000_FF;
+    sprite.palette[2] = 0x0000FF_FF;
+    sprite.add_layer("a");
+
+    let mut image = Receiver::new("", 2, 2);
+    image.palette[1] = 0x0000F0_FF;
+    image.palette[2] = 0xF00000_FF;
+    image.add_layer("load");
+    image.data[0].frames[0].page.copy_from_slice(&[1, 2, 0, 0]);
+    image.data[0].frames[0].transparent = None;
+
+    let layer = to_layer(&sprite, &image, "drop");
+    sprite.data.push(layer);
+    assert_eq!(sprite.data.len(), 2);
+    assert_eq!(sprite.data[1].frames.len(), 1);
+    assert_eq!(sprite.page(1, 0).page, &[2, 1, 0]);
+}
@@ -0,0 +1,317 @@
+// Native document format, everything is little-endian:
+//
+// magic       "ASPR"
+// version     u16
+// width       u32
+// height      u32
+// layer       u32   current layer
+// frame       u32   current frame
+// name        str
+// palette     transparent: opt, size u16 (since version 5), 256 x u32 (RGBA)
+// layers      u32 count, then for every layer:
+//     name    str
+//     flags   u8    VISIBLE | LOCK
+//     frames  u32 count, then for every frame:
+//         transparent opt
+//         page        width * height bytes
+// durations   u32 count, u32 milliseconds each (since version 2)
+// cycles      u32 count, then low u8, high u8, rate u16, flags u8 ACTIVE | REVERSE
+//             for every colour cycling range (since version 3)
+// tiles       u32 count, then for every tile with properties (since version 4):
+//     id      u32
+//     props   u32 count, then name str, kind u8 and a value for every property:
+//             BOOL u8, INT i32 or STR str
+//
+// `str` is a `u32` length followed by UTF-8 bytes,
+// `opt` is a `u8` flag followed by a `u8` value.
+
+use std::io::{self, Read, Write};
+use byteorder::{LittleEndian as LE, ReadBytesExt, WriteBytesExt};
+
+use draw::Frame;
+use tool::{Receiver, Layer, Cycle, Property, Properties};
+use super::{invalid, read_bytes};
+
+pub const EXTENSION: &str = "aspr";
+
+const MAGIC: &[u8; 4] = b"ASPR";
+const VERSION: u16 = 5;
+
+const VISIBLE: u8 = 1 << 0;
+const LOCK: u8 = 1 << 1;
+
+const ACTIVE: u8 = 1 << 0;
+const REVERSE: u8 = 1 << 1;
+
+const BOOL: u8 = 0;
+const INT: u8 = 1;
+const STR: u8 = 2;
+
+fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    w.write_u32::<LE>(s.len() as u32)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_str<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = r.read_u32::<LE>()? as usize;
+    let buf = read_bytes(r, len)?;
+    String::from_utf8(buf).or_else(|_| invalid("name is not valid UTF-8"))
+}
+
+fn write_opt<W: Write>(w: &mut W, v: Option<u8>) -> io::Result<()> {
+    w.write_u8(v.is_some() as u8)?;
+    w.write_u8(v.unwrap_or(0))
+}
+
+fn read_opt<R: Read>(r: &mut R) -> io::Result<Option<u8>> {
+    let is = r.read_u8()? != 0;
+    let v = r.read_u8()?;
+    Ok(if is { Some(v) } else { None })
+}
+
+pub fn write<W: Write>(w: &mut W, sprite: &Receiver) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_u16::<LE>(VERSION)?;
+    w.write_u32::<LE>(sprite.width as u32)?;
+    w.write_u32::<LE>(sprite.height as u32)?;
+    w.write_u32::<LE>(sprite.layer as u32)?;
+    w.write_u32::<LE>(sprite.frame as u32)?;
+    write_str(w, &sprite.name)?;
+
+    write_opt(w, sprite.palette.transparent)?;
+    w.write_u16::<LE>(sprite.palette.size.max(1).min(256) as u16)?;
+    for &c in sprite.palette.iter() {
+        w.write_u32::<LE>(c)?;
+    }
+
+    w.write_u32::<LE>(sprite.data.len() as u32)?;
+    for layer in &sprite.data {
+        write_str(w, &layer.name)?;
+        let mut flags = 0;
+        if layer.visible { flags |= VISIBLE }
+        if layer.lock { flags |= LOCK }
+        w.write_u8(flags)?;
+
+        w.write_u32::<LE>(layer.frames.len() as u32)?;
+        for page in &layer.frames {
+            write_opt(w, page.transparent)?;
+            w.write_all(&page.page)?;
+        }
+    }
+
+    w.write_u32::<LE>(sprite.durations.len() as u32)?;
+    for &duration in &sprite.durations {
+        w.write_u32::<LE>(duration)?;
+    }
+
+    w.write_u32::<LE>(sprite.cycles.len() as u32)?;
+    for cycle in &sprite.cycles {
+        w.write_u8(cycle.low)?;
+        w.write_u8(cycle.high)?;
+        w.write_u16::<LE>(cycle.rate)?;
+        let mut flags = 0;
+        if cycle.active { flags |= ACTIVE }
+        if cycle.reverse { flags |= REVERSE }
+        w.write_u8(flags)?;
+    }
+
+    w.write_u32::<LE>(sprite.tiles.len() as u32)?;
+    for (&id, props) in &sprite.tiles {
+        w.write_u32::<LE>(id as u32)?;
+        w.write_u32::<LE>(props.len() as u32)?;
+        for (name, value) in props {
+            write_str(w, name)?;
+            match *value {
+                Property::Bool(v) => { w.write_u8(BOOL)?; w.write_u8(v as u8)?; }
+                Property::Int(v) => { w.write_u8(INT)?; w.write_i32::<LE>(v)?; }
+                Property::Str(ref v) => { w.write_u8(STR)?; write_str(w, v)?; }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn read<R: Read>(r: &mut R) -> io::Result<Receiver> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return invalid("not an asprite document");
+    }
+    let version = r.read_u16::<LE>()?;
+    if version > VERSION {
+        return invalid("document was saved by a newer version");
+    }
+
+    let width = r.read_u32::<LE>()? as usize;
+    let height = r.read_u32::<LE>()? as usize;
+    let current_layer = r.read_u32::<LE>()? as usize;
+    let current_frame = r.read_u32::<LE>()? as usize;
+    let name = read_str(r)?;
+    let size = match width.checked_mul(height) {
+        Some(size) => size,
+        None => return invalid("bad document size"),
+    };
+
+    let mut sprite = Receiver::new(&name, width, height);
+
+    sprite.palette.transparent = read_opt(r)?;
+    if version >= 5 {
+        let size = r.read_u16::<LE>()? as usize;
+        if size == 0 || size > 256 {
+            return invalid("bad palette size");
+        }
+        sprite.palette.size = size;
+    }
+    for c in sprite.palette.iter_mut() {
+        *c = r.read_u32::<LE>()?;
+    }
+
+    let layers = r.read_u32::<LE>()?;
+    for _ in 0..layers {
+        let mut layer = Layer::new(&read_str(r)?);
+        let flags = r.read_u8()?;
+        layer.visible = flags & VISIBLE != 0;
+        layer.lock = flags & LOCK != 0;
+
+        let frames = r.read_u32::<LE>()?;
+        for _ in 0..frames {
+            let transparent = read_opt(r)?;
+            let page = read_bytes(r, size)?;
+            layer.push(Frame { page, transparent, width, height });
+        }
+        if layer.frames.is_empty() {
+            return invalid("layer without frames");
+        }
+        sprite.data.push(layer);
+    }
+
+    if sprite.data.is_empty() {
+        return invalid("document without layers");
+    }
+
+    if version >= 2 {
+        let count = r.read_u32::<LE>()?;
+        for _ in 0..count {
+            sprite.durations.push(r.read_u32::<LE>()?);
+        }
+    }
+
+    if version >= 3 {
+        let count = r.read_u32::<LE>()?;
+        for _ in 0..count {
+            let low = r.read_u8()?;
+            let high = r.read_u8()?;
+            let rate = r.read_u16::<LE>()?;
+            let flags = r.read_u8()?;
+            sprite.cycles.push(Cycle {
+                low, high, rate,
+                active: flags & ACTIVE != 0,
+                reverse: flags & REVERSE != 0,
+            });
+        }
+    }
+
+    if version >= 4 {
+        let count = r.read_u32::<LE>()?;
+        for _ in 0..count {
+            let id = r.read_u32::<LE>()? as usize;
+            let mut props = Properties::new();
+            for _ in 0..r.read_u32::<LE>()? {
+                let name = read_str(r)?;
+                let value = match r.read_u8()? {
+                    BOOL => Property::Bool(r.read_u8()? != 0),
+                    INT => Property::Int(r.read_i32::<LE>()?),
+                    STR => Property::Str(read_str(r)?),
+                    _ => return invalid("unknown property kind"),
+                };
+                props.insert(name, value);
+            }
+            sprite.tiles.insert(id, props);
+        }
+    }
+
+    sprite.layer = current_layer.min(sprite.data.len() - 1);
+    sprite.frame = current_frame.min(sprite.data[sprite.layer].frames.len() - 1);
+
+    Ok(sprite)
+}
+
+#[test]
+fn round_trip() {
+    let mut sprite = Receiver::new("test", 3, 2);
+    sprite.add_layer("a");
+    sprite.add_layer("b");
+    sprite.data[1].visible = false;
+    sprite.data[1].lock = true;
+    sprite.data[1].push(Frame::new(3, 2));
+    sprite.data[1].frames[1].page[4] = 7;
+    sprite.data[1].frames[1].transparent = None;
+    sprite.palette[7] = 0x112233_FF;
+    sprite.palette.transparent = Some(3);
+    sprite.palette.size = 12;
+    sprite.layer = 1;
+    sprite.frame = 1;
+    sprite.set_duration(1, 250);
+    sprite.cycles.push(Cycle { low: 4, high: 7, rate: 8192, active: true, reverse: false });
+    let mut props = Properties::new();
+    props.insert("collision".to_string(), Property::Bool(true));
+    props.insert("kind".to_string(), Property::Str("wall".to_string()));
+    sprite.tiles.insert(5, props);
+
+    let mut buf = Vec::new();
+    write(&mut buf, &sprite).unwrap();
+    let m = read(&mut &buf[..]).unwrap();
+
+    assert_eq!(m.name, "test");
+    assert_eq!((m.width, m.height), (3, 2));
+    assert_eq!((m.layer, m.frame), (1, 1));
+    assert_eq!(m.palette[7], 0x112233_FF);
+    assert_eq!(m.palette.transparent, Some(3));
+    assert_eq!(m.palette.size, 12);
+    assert_eq!(m.data.len(), 2);
+    assert_eq!(m.data[0].name, "a");
+    assert!(m.data[0].visible && !m.data[0].lock);
+    assert!(!m.data[1].visible && m.data[1].lock);
+    assert_eq!(m.data[1].frames.len(), 2);
+    assert_eq!(m.data[1].frames[1].page, &[0, 0, 0, 0, 7, 0]);
+    assert_eq!(m.data[1].frames[1].transparent, None);
+    assert_eq!(m.data[1].frames[0].transparent, Some(0));
+    assert_eq!(m.durations, &[100, 250]);
+    assert_eq!(m.cycles, sprite.cycles);
+    assert_eq!(m.tiles, sprite.tiles);
+
+    // an empty palette still has its first entry
+    sprite.palette.size = 0;
+    let mut buf = Vec::new();
+    write(&mut buf, &sprite).unwrap();
+    assert_eq!(read(&mut &buf[..]).unwrap().palette.size, 1);
+}
+
+#[test]
+fn read_broken_lengths() {
+    let header = |w: u32, h: u32, name: u32| {
+        let mut buf = MAGIC.to_vec();
+        buf.write_u16::<LE>(VERSION).unwrap();
+        for &v in &[w, h, 0, 0, name] {
+            buf.write_u32::<LE>(v).unwrap();
+        }
+        buf
+    };
+
+    // a name claiming 4 GiB
+    let buf = header(1, 1, !0);
+    assert_eq!(read(&mut &buf[..]).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+
+    // one 65536 x 65536 frame without its pixels
+    let mut buf = header(0x10000, 0x10000, 0);
+    write_opt(&mut buf, None).unwrap();
+    buf.write_u16::<LE>(256).unwrap();
+    buf.extend_from_slice(&[0; 256 * 4]);
+    buf.write_u32::<LE>(1).unwrap();
+    write_str(&mut buf, "a").unwrap();
+    buf.write_u8(VISIBLE).unwrap();
+    buf.write_u32::<LE>(1).unwrap();
+    write_opt(&mut buf, None).unwrap();
+    buf.extend_from_slice(&[0; 16]);
+    assert_eq!(read(&mut &buf[..]).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+}
@@ -0,0 +1,718 @@
+use std::io::{self, Read, Write, BufRead, BufReader};
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use draw::{Palette, color};
+use draw::color::{Space, Distance};
+use tool::Receiver;
+use super::{Error, invalid, nearest, to_rgba, from_rgba};
+use super::quantize::{self, Quantizer};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// GIMP `.gpl`
+    Gimp,
+    /// JASC `.pal`
+    Jasc,
+    /// Paint.NET `.txt`
+    PaintNet,
+    /// Photoshop `.act`
+    Act,
+    /// Plain `.hex`, one RRGGBB per line
+    Hex,
+}
+
+impl Kind {
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "gpl" => Some(Kind::Gimp),
+            "pal" => Some(Kind::Jasc),
+            "txt" => Some(Kind::PaintNet),
+            "act" => Some(Kind::Act),
+            "hex" => Some(Kind::Hex),
+            _ => None,
+        }
+    }
+}
+
+pub const EXTENSIONS: &str = "gpl,pal,txt,act,hex";
+
+/// What happens to the pixels when a palette replaces the sprite one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Import {
+    KeepIndices,
+    /// Every pixel moves to the nearest colour of the new palette.
+    Remap,
+}
+
+fn broken<T>(msg: &str) -> Result<T, Error> {
+    Err(Error::Decode(msg.to_string()))
+}
+
+fn parse_rgb<'a, I: Iterator<Item=&'a str>>(mut words: I) -> io::Result<u32> {
+    let mut c = [0u8; 3];
+    for c in &mut c {
+        *c = match words.next().map(|w| w.parse()) {
+            Some(Ok(v)) => v,
+            _ => return invalid("bad colour"),
+        };
+    }
+    Ok(from_rgba(c[0], c[1], c[2], 0xFF))
+}
+
+fn parse_hex(s: &str) -> io::Result<u32> {
+    let s = if s.starts_with('#') { &s[1..] } else { s };
+    match u32::from_str_radix(s, 16) {
+        Ok(c) if s.len() == 6 => Ok(c << 8 | 0xFF),
+        // Paint.NET stores AARRGGBB
+        Ok(c) if s.len() == 8 => Ok(c << 8 | c >> 24),
+        _ => invalid("bad hex colour"),
+    }
+}
+
+fn next_line<I: Iterator<Item=io::Result<String>>>(lines: &mut I) -> io::Result<String> {
+    lines.next().unwrap_or_else(|| invalid("unexpected end of file"))
+}
+
+pub fn read<R: Read>(r: R, kind: Kind) -> Result<Palette<u32>, Error> {
+    let mut palette = Palette::new(0x000000_FF, None);
+    palette.size = 0;
+
+    if kind == Kind::Act {
+        let mut data = Vec::new();
+        BufReader::new(r).read_to_end(&mut data)?;
+        if data.len() < 768 {
+            return broken("ACT palette is too short");
+        }
+        for (i, c) in data[..768].chunks(3).enumerate() {
+            palette[i as u8] = from_rgba(c[0], c[1], c[2], 0xFF);
+        }
+        palette.size = 256;
+        // optional trailer: big-endian count and transparent index
+        if data.len() >= 772 {
+            let count = (data[768] as usize) << 8 | data[769] as usize;
+            let transparent = (data[770] as usize) << 8 | data[771] as usize;
+            if count > 0 && count <= 256 {
+                palette.size = count;
+            }
+            if transparent < 256 {
+                palette.transparent = Some(transparent as u8);
+            }
+        }
+        return Ok(palette);
+    }
+
+    let mut lines = BufReader::new(r).lines();
+    let mut colors = Vec::new();
+
+    match kind {
+        Kind::Gimp => {
+            if next_line(&mut lines)?.trim() != "GIMP Palette" {
+                return broken("not a GIMP palette");
+            }
+            while let Some(line) = lines.next() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') ||
+                    line.starts_with("Name:") || line.starts_with("Columns:")
+                {
+                    continue;
+                }
+                colors.push(parse_rgb(line.split_whitespace())?);
+            }
+        }
+        Kind::Jasc => {
+            if next_line(&mut lines)?.trim() != "JASC-PAL" {
+                return broken("not a JASC palette");
+            }
+            next_line(&mut lines)?; // version
+            let count: usize = match next_line(&mut lines)?.trim().parse() {
+                Ok(count) => count,
+                Err(_) => return broken("bad colour count"),
+            };
+            for _ in 0..count {
+                colors.push(parse_rgb(next_line(&mut lines)?.split_whitespace())?);
+            }
+        }
+        Kind::PaintNet | Kind::Hex => {
+            while let Some(line) = lines.next() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty() || line.starts_with(';') {
+                    continue;
+                }
+                colors.push(parse_hex(line)?);
+            }
+        }
+        Kind::Act => unreachable!(),
+    }
+
+    if colors.is_empty() {
+        return broken("palette without colours");
+    }
+    if colors.len() > 256 {
+        return Err(Error::TooManyColors(colors.len()));
+    }
+    for (i, &c) in colors.iter().enumerate() {
+        palette[i as u8] = c;
+    }
+    palette.size = colors.len();
+    Ok(palette)
+}
+
+pub fn write<W: Write>(w: &mut W, palette: &Palette<u32>, kind: Kind) -> io::Result<()> {
+    let colors = palette.iter().take(palette.size);
+    match kind {
+        Kind::Gimp => {
+            writeln!(w, "GIMP Palette")?;
+            writeln!(w, "Columns: 16")?;
+            writeln!(w, "#")?;
+            for (i, &c) in colors.enumerate() {
+                let [r, g, b, _] = to_rgba(c);
+                writeln!(w, "{:3} {:3} {:3}\tIndex {}", r, g, b, i)?;
+            }
+        }
+        Kind::Jasc => {
+            write!(w, "JASC-PAL\r\n0100\r\n{}\r\n", palette.size)?;
+            for &c in colors {
+                let [r, g, b, _] = to_rgba(c);
+                write!(w, "{} {} {}\r\n", r, g, b)?;
+            }
+        }
+        Kind::PaintNet => {
+            writeln!(w, "; paint.net Palette File")?;
+            writeln!(w, "; Colors: {}", palette.size)?;
+            for &c in colors {
+                let [r, g, b, a] = to_rgba(c);
+                writeln!(w, "{:02X}{:02X}{:02X}{:02X}", a, r, g, b)?;
+            }
+        }
+        Kind::Hex => {
+            for &c in colors {
+                writeln!(w, "{:06x}", c >> 8)?;
+            }
+        }
+        Kind::Act => {
+            let mut data = [0u8; 772];
+            for (i, &c) in colors.enumerate() {
+                data[i * 3..i * 3 + 3].copy_from_slice(&to_rgba(c)[..3]);
+            }
+            let t = palette.transparent.map_or(0xFFFF, |t| t as usize);
+            data[768] = (palette.size >> 8) as u8;
+            data[769] = palette.size as u8;
+            data[770] = (t >> 8) as u8;
+            data[771] = t as u8;
+            w.write_all(&data)?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves every pixel through the table, transparent ones go to
+/// `transparent` if the new palette has one.
+fn translate(sprite: &mut Receiver, table: &[u8; 256], transparent: Option<u8>) {
+    for layer in &mut sprite.data {
+        for page in &mut layer.frames {
+            let t = page.transparent;
+            let new_t = t.map(|t| transparent.unwrap_or(t));
+            for c in &mut page.page {
+                *c = if Some(*c) == t { new_t.unwrap() } else { table[*c as usize] };
+            }
+            page.transparent = new_t;
+        }
+    }
+}
+
+fn replace(sprite: &mut Receiver, palette: &Palette<u32>) {
+    sprite.palette.map = palette.map;
+    sprite.palette.size = palette.size;
+    if palette.transparent.is_some() {
+        sprite.palette.transparent = palette.transparent;
+    }
+}
+
+/// Puts the palette into the sprite, remapping pixels if asked to.
+pub fn apply(sprite: &mut Receiver, palette: &Palette<u32>, mode: Import) {
+    if mode == Import::Remap {
+        let colors = &palette.map[..palette.size.max(1)];
+        let mut table = [0u8; 256];
+        for (i, c) in table.iter_mut().enumerate() {
+            *c = nearest(colors, sprite.palette[i as u8]) as u8;
+        }
+        translate(sprite, &table, palette.transparent);
+    }
+    replace(sprite, palette);
+}
+
+/// How a sprite moves onto another palette.
+#[derive(Clone, Debug)]
+pub struct Convert {
+    pub distance: Distance,
+    /// Sprite entries sent to a chosen entry of the new palette instead of the nearest one.
+    pub overrides: BTreeMap<u8, u8>,
+}
+
+/// Entry of the new palette for every entry of the sprite one. The
+/// transparent entry of the new palette is never picked as the nearest.
+pub fn convert_table(sprite: &Receiver, palette: &Palette<u32>, opts: &Convert) -> [u8; 256] {
+    let candidates: Vec<u8> = (0..palette.size.max(1).min(256))
+        .map(|i| i as u8)
+        .filter(|&i| Some(i) != palette.transparent)
+        .collect();
+
+    let mut table = [0u8; 256];
+    for (i, c) in table.iter_mut().enumerate() {
+        let color = sprite.palette[i as u8];
+        *c = match opts.overrides.get(&(i as u8)) {
+            Some(&to) => to,
+            None => candidates.iter().cloned()
+                .map(|j| (j, color::distance(color, palette[j], opts.distance)))
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+                .map_or(0, |(j, _)| j),
+        };
+    }
+    table
+}
+
+/// Replaces the palette, every layer and frame keeps looking as close
+/// to before as the new colours allow.
+pub fn convert(sprite: &mut Receiver, palette: &Palette<u32>, opts: &Convert) {
+    let table = convert_table(sprite, palette, opts);
+    translate(sprite, &table, palette.transparent);
+    replace(sprite, palette);
+}
+
+/// Order of colours for sorting a palette.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortBy {
+    /// Greys first from dark to light, then by hue around the wheel.
+    Hue,
+    Luminance,
+    /// Most drawn first, only for palettes of a sprite.
+    Usage,
+}
+
+fn compare(a: u32, b: u32, by: SortBy) -> Ordering {
+    let lum = |c: u32| color::luminance(c);
+    let by_lum = lum(a).partial_cmp(&lum(b)).unwrap_or(Ordering::Equal);
+    match by {
+        SortBy::Luminance => by_lum,
+        SortBy::Usage => Ordering::Equal,
+        SortBy::Hue => {
+            let key = |c: u32| {
+                let (h, s, _) = color::to_hsv(c);
+                if s == 0.0 { -1.0 } else { h }
+            };
+            key(a).partial_cmp(&key(b)).unwrap_or(Ordering::Equal).then(by_lum)
+        }
+    }
+}
+
+/// Sorts colours in place, the sort is stable. Plain colours have no usage,
+/// so `SortBy::Usage` keeps them as they are.
+pub fn sort(colors: &mut [u32], by: SortBy) {
+    colors.sort_by(|&a, &b| compare(a, b, by));
+}
+
+/// How a ramp blends its two ends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ramp {
+    pub space: Space,
+    /// Degrees the hue turns toward yellow in the highlights and toward
+    /// blue in the shadows, 0 for a plain blend.
+    pub hue_shift: f32,
+}
+
+const WARM: f32 = 60.0;
+const COOL: f32 = 240.0;
+
+/// Turns the hue toward warm for positive `amount`, cool for negative.
+fn shift_hue(c: u32, amount: f32) -> u32 {
+    let (h, s, v) = color::to_hsv(c);
+    if s == 0.0 || amount == 0.0 {
+        return c;
+    }
+    let d = color::hue_delta(h, if amount > 0.0 { WARM } else { COOL });
+    color::from_hsv(h + amount.abs().min(d.abs()) * d.signum(), s, v, c as u8)
+}
+
+/// Ramp from the colour at `start` to the one at `end`, over the entries
+/// between them in either direction. A `count` other than 0 spreads that many
+/// colours evenly from `start` to `end` and leaves the entries in between,
+/// counts past the whole span fill it. The palette grows to hold the ramp.
+pub fn ramp(palette: &mut Palette<u32>, start: u8, end: u8, count: usize, opts: Ramp) {
+    let (a, b) = (palette[start], palette[end]);
+    let (from, to) = (start as isize, end as isize);
+    let span = (to - from).abs() as usize;
+    let count = if count == 0 { span + 1 } else { count.min(span + 1) };
+    let slots: Vec<u8> = (0..count)
+        .map(|k| {
+            let steps = (count - 1).max(1) as f32;
+            (from as f32 + (to - from) as f32 * k as f32 / steps).round() as u8
+        })
+        .collect();
+    let last = slots.len().saturating_sub(1);
+    if last == 0 {
+        return;
+    }
+
+    // shadows are on the darker end
+    let lighter = if color::luminance(b) >= color::luminance(a) { 1.0 } else { -1.0 };
+    for (j, &i) in slots.iter().enumerate() {
+        let t = j as f32 / last as f32;
+        palette[i] = if j == 0 {
+            a
+        } else if j == last {
+            b
+        } else {
+            let c = color::mix(a, b, t, opts.space);
+            shift_hue(c, opts.hue_shift * (2.0 * t - 1.0) * lighter)
+        };
+    }
+    let max = slots.iter().cloned().max().unwrap_or(0) as usize;
+    palette.size = palette.size.max(max + 1);
+}
+
+/// How an image is turned into a palette.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extract {
+    /// Every colour of the image, fails if there are more than 256.
+    Unique,
+    /// At most that many colours picked by the quantizer.
+    Reduce(usize, Quantizer),
+}
+
+/// Palette of the opaque colours in the pixels, in order of first appearance
+/// unless reduced. Fully transparent pixels are skipped.
+pub fn extract(pixels: &[u32], mode: Extract) -> Result<Palette<u32>, Error> {
+    let histogram = quantize::histogram(pixels);
+    let colors = match mode {
+        Extract::Unique => histogram.iter().map(|&(c, _)| c).collect(),
+        Extract::Reduce(count, q) => quantize::reduce(&histogram, count.min(256), q),
+    };
+    if colors.len() > 256 {
+        return Err(Error::TooManyColors(colors.len()));
+    }
+
+    let mut palette = Palette::new(0x000000_FF, None);
+    for (i, &c) in colors.iter().enumerate() {
+        palette[i as u8] = c;
+    }
+    palette.size = colors.len().max(1);
+    Ok(palette)
+}
+
+/// Indices drawn in any frame of any layer, transparent ones included.
+pub fn used(sprite: &Receiver) -> [bool; 256] {
+    let mut used = [false; 256];
+    if let Some(t) = sprite.palette.transparent {
+        used[t as usize] = true;
+    }
+    for page in sprite.data.iter().flat_map(|layer| &layer.frames) {
+        if let Some(t) = page.transparent {
+            used[t as usize] = true;
+        }
+        for &c in &page.page {
+            used[c as usize] = true;
+        }
+    }
+    used
+}
+
+/// How many pixels of every frame in every layer use each index.
+pub fn usage(sprite: &Receiver) -> [usize; 256] {
+    let mut count = [0; 256];
+    for page in sprite.data.iter().flat_map(|layer| &layer.frames) {
+        for &c in &page.page {
+            count[c as usize] += 1;
+        }
+    }
+    count
+}
+
+fn sort_indices(sprite: &Receiver, indices: &mut [u8], by: SortBy) {
+    if by == SortBy::Usage {
+        let count = usage(sprite);
+        indices.sort_by(|&a, &b| count[b as usize].cmp(&count[a as usize]));
+    } else {
+        let palette = &sprite.palette;
+        indices.sort_by(|&a, &b| compare(palette[a], palette[b], by));
+    }
+}
+
+/// Rebuilds the palette so entry `i` is the old entry `order[i]` and moves
+/// every pixel and transparent index along. Old entries missing from
+/// `order` must not be in use.
+pub fn remap(sprite: &mut Receiver, order: &[u8]) {
+    let mut table = [0u8; 256];
+    for (new, &old) in order.iter().enumerate() {
+        table[old as usize] = new as u8;
+    }
+
+    for page in sprite.data.iter_mut().flat_map(|layer| &mut layer.frames) {
+        for c in &mut page.page {
+            *c = table[*c as usize];
+        }
+        page.transparent = page.transparent.map(|t| table[t as usize]);
+    }
+
+    let map = sprite.palette.map;
+    for (new, &old) in order.iter().enumerate() {
+        sprite.palette[new as u8] = map[old as usize];
+    }
+    sprite.palette.size = order.len().max(1);
+    sprite.palette.transparent = sprite.palette.transparent.map(|t| table[t as usize]);
+}
+
+/// Drops the palette entries no frame uses, keeping the rest in order or
+/// sorting them. Returns the new palette size.
+pub fn compact(sprite: &mut Receiver, by: Option<SortBy>) -> usize {
+    let used = used(sprite);
+    let mut order: Vec<u8> = (0..256).filter(|&i| used[i]).map(|i| i as u8).collect();
+    if let Some(by) = by {
+        sort_indices(sprite, &mut order, by);
+    }
+    remap(sprite, &order);
+    order.len()
+}
+
+/// Like `remap` for a permutation of the first `order.len()` entries,
+/// the ones after it and the palette size stay as they are.
+pub fn reorder(sprite: &mut Receiver, order: &[u8]) {
+    let size = sprite.palette.size;
+    let full: Vec<u8> = order.iter().cloned()
+        .chain((order.len()..256).map(|i| i as u8))
+        .collect();
+    remap(sprite, &full);
+    sprite.palette.size = size;
+}
+
+/// Moves an entry to another index, shifting the ones in between.
+pub fn move_entry(sprite: &mut Receiver, from: u8, to: u8) {
+    let len = from.max(to) as usize + 1;
+    let mut order: Vec<u8> = (0..len).map(|i| i as u8).collect();
+    order.remove(from as usize);
+    order.insert(to as usize, from);
+    reorder(sprite, &order);
+}
+
+pub fn swap_entries(sprite: &mut Receiver, a: u8, b: u8) {
+    let len = a.max(b) as usize + 1;
+    let mut order: Vec<u8> = (0..len).map(|i| i as u8).collect();
+    order.swap(a as usize, b as usize);
+    reorder(sprite, &order);
+}
+
+/// Sorts the entries within the palette size, pixels keep their colours.
+pub fn sort_entries(sprite: &mut Receiver, by: SortBy) {
+    let mut order: Vec<u8> = (0..sprite.palette.size.min(256)).map(|i| i as u8).collect();
+    sort_indices(sprite, &mut order, by);
+    reorder(sprite, &order);
+}
+
+#[test]
+fn text_round_trip() {
+    let mut palette = Palette::new(0x000000_FF, None);
+    palette.size = 3;
+    palette[1] = 0xFF8000_FF;
+    palette[2] = 0x0080FF_FF;
+
+    for &kind in &[Kind::Gimp, Kind::Jasc, Kind::PaintNet, Kind::Hex, Kind::Act] {
+        let mut buf = Vec::new();
+        write(&mut buf, &palette, kind).unwrap();
+        let m = read(&buf[..], kind).unwrap();
+        assert_eq!(m.size, 3, "{:?}", kind);
+        assert_eq!(&m.map[..3], &palette.map[..3], "{:?}", kind);
+    }
+}
+
+#[test]
+fn read_gimp() {
+    let src = "GIMP Palette\nName: test\nColumns: 4\n#\n# comment\n255   0   0\tRed\n  0 255   0\n";
+    let m = read(src.as_bytes(), Kind::Gimp).unwrap();
+    assert_eq!(m.size, 2);
+    assert_eq!(m[0], 0xFF0000_FF);
+    assert_eq!(m[1], 0x00FF00_FF);
+}
+
+#[test]
+fn empty_palettes() {
+    let empty = [
+        ("GIMP Palette\nName: empty\n#\n", Kind::Gimp),
+        ("JASC-PAL\n0100\n0\n", Kind::Jasc),
+        ("; none\n", Kind::PaintNet),
+        ("", Kind::Hex),
+    ];
+    for &(src, kind) in &empty {
+        match read(src.as_bytes(), kind) {
+            Err(Error::Decode(_)) => (),
+            Err(err) => panic!("{:?}: {}", kind, err),
+            Ok(_) => panic!("{:?}: expected an error", kind),
+        }
+    }
+}
+
+#[test]
+fn too_many_colors() {
+    let src: String = (0..300).map(|i| format!("{:06x}\n", i)).collect();
+    match read(src.as_bytes(), Kind::Hex) {
+        Err(Error::TooManyColors(300)) => (),
+        _ => panic!("expected too many colours"),
+    }
+}
+
+#[test]
+fn apply_remap() {
+    let mut sprite = Receiver::new("", 3, 1);
+    sprite.palette[1] = 0xF00000_FF;
+    sprite.palette[2] = 0x0000F0_FF;
+    sprite.add_layer("a");
+    sprite.data[0].frames[0].page.copy_from_slice(&[0, 1, 2]);
+
+    let mut palette = Palette::new(0x000000_FF, None);
+    palette.size = 3;
+    palette[1] = 0x0000FF_FF;
+    palette[2] = 0xFF0000_FF;
+
+    apply(&mut sprite, &palette, Import::Remap);
+    assert_eq!(sprite.page(0, 0).page, &[0, 2, 1]);
+    assert_eq!(sprite.palette[1], 0x0000FF_FF);
+}
+
+#[test]
+fn extract_colors() {
+    let pixels = [0x0000FF_FF, 0xFF0000_FF, 0x0000FF_FF, 0x808080_80, 0x00FF00_00];
+    let m = extract(&pixels, Extract::Unique).unwrap();
+    assert_eq!(m.size, 3);
+    assert_eq!(&m.map[..3], &[0x0000FF_FF, 0xFF0000_FF, 0x808080_FF]);
+
+    let mut colors = m.map[..3].to_vec();
+    sort(&mut colors, SortBy::Hue);
+    assert_eq!(colors, &[0x808080_FF, 0xFF0000_FF, 0x0000FF_FF]);
+    sort(&mut colors, SortBy::Luminance);
+    assert_eq!(colors, &[0x0000FF_FF, 0xFF0000_FF, 0x808080_FF]);
+
+    let noise: Vec<u32> = (0..300).map(|i| (i as u32) << 8 | 0xFF).collect();
+    assert!(extract(&noise, Extract::Unique).is_err());
+    let m = extract(&noise, Extract::Reduce(16, Quantizer::MedianCut)).unwrap();
+    assert_eq!(m.size, 16);
+}
+
+#[test]
+fn compact_used() {
+    let mut sprite = Receiver::new("", 3, 1);
+    sprite.palette.size = 8;
+    for i in 0..8 {
+        sprite.palette[i] = from_rgba(i * 30, i * 30, i * 30, 0xFF);
+    }
+    sprite.palette.transparent = Some(0);
+    sprite.add_layer("a");
+    sprite.data[0].frames[0].page.copy_from_slice(&[5, 0, 7]);
+    sprite.add_layer("b");
+    sprite.data[1].frames[0].page.copy_from_slice(&[3, 0, 0]);
+
+    assert_eq!(compact(&mut sprite, None), 4);
+    assert_eq!(&sprite.palette.map[..4], &[0x000000_FF, 0x5A5A5A_FF, 0x969696_FF, 0xD2D2D2_FF]);
+    assert_eq!(sprite.page(0, 0).page, &[2, 0, 3]);
+    assert_eq!(sprite.page(1, 0).page, &[1, 0, 0]);
+    assert_eq!(sprite.palette.transparent, Some(0));
+}
+
+#[test]
+fn reorder_keeps_colors() {
+    let mut sprite = Receiver::new("", 4, 1);
+    sprite.palette.size = 4;
+    sprite.palette[1] = 0xFF0000_FF;
+    sprite.palette[2] = 0x00FF00_FF;
+    sprite.palette[3] = 0x0000FF_FF;
+    sprite.palette[9] = 0x123456_FF;
+    sprite.palette.transparent = Some(0);
+    sprite.add_layer("a");
+    sprite.data[0].frames[0].page.copy_from_slice(&[3, 3, 1, 0]);
+
+    let colors = |m: &Receiver| -> Vec<u32> {
+        m.page(0, 0).page.iter().map(|&c| m.palette[c]).collect()
+    };
+    let before = colors(&sprite);
+
+    move_entry(&mut sprite, 3, 0);
+    assert_eq!(sprite.page(0, 0).page, &[0, 0, 2, 1]);
+    assert_eq!(sprite.palette.transparent, Some(1));
+    assert_eq!(sprite.page(0, 0).transparent, Some(1));
+    assert_eq!(colors(&sprite), before);
+
+    swap_entries(&mut sprite, 0, 2);
+    assert_eq!(sprite.page(0, 0).page, &[2, 2, 0, 1]);
+    assert_eq!(colors(&sprite), before);
+
+    sort_entries(&mut sprite, SortBy::Usage);
+    assert_eq!(sprite.page(0, 0).page, &[0, 0, 1, 2]);
+    assert_eq!(colors(&sprite), before);
+    assert_eq!((sprite.palette.size, sprite.palette[9]), (4, 0x123456_FF));
+}
+
+#[test]
+fn ramps() {
+    let mut palette = Palette::new(0x000000_FF, None);
+    palette.size = 2;
+    palette[4] = 0xFFFFFF_FF;
+    let plain = Ramp { space: Space::Rgb, hue_shift: 0.0 };
+
+    ramp(&mut palette, 0, 4, 0, plain);
+    assert_eq!(&palette.map[..5], &[0x000000_FF, 0x404040_FF, 0x808080_FF, 0xBFBFBF_FF, 0xFFFFFF_FF]);
+    assert_eq!(palette.size, 5);
+
+    // backwards over every other entry, the ones between are kept
+    palette[2] = 0x00FF00_FF;
+    ramp(&mut palette, 4, 0, 3, plain);
+    assert_eq!(&palette.map[..5], &[0x000000_FF, 0x404040_FF, 0x808080_FF, 0xBFBFBF_FF, 0xFFFFFF_FF]);
+    assert_eq!(palette.size, 5);
+
+    // a count past the span fills it and goes no further
+    palette[5] = 0x00FF00_FF;
+    ramp(&mut palette, 0, 2, 9, plain);
+    assert_eq!(&palette.map[..6], &[0x000000_FF, 0x404040_FF, 0x808080_FF, 0xBFBFBF_FF, 0xFFFFFF_FF, 0x00FF00_FF]);
+    assert_eq!(palette.size, 5);
+
+    palette[10] = 0x400000_FF;
+    palette[14] = 0xFF8080_FF;
+    ramp(&mut palette, 10, 14, 0, Ramp { space: Space::Hsv, hue_shift: 20.0 });
+    let hue = |i: u8| color::to_hsv(palette[i]).0;
+    assert!(hue(11) > 300.0, "shadows lean blue: {}", hue(11));
+    assert!(hue(13) > 0.0 && hue(13) < 60.0, "highlights lean yellow: {}", hue(13));
+    assert_eq!(palette[14], 0xFF8080_FF);
+}
+
+#[test]
+fn convert_perceptual() {
+    let mut sprite = Receiver::new("", 4, 1);
+    sprite.palette.size = 4;
+    sprite.palette[1] = 0xE01010_FF;
+    sprite.palette[2] = 0x1010E0_FF;
+    sprite.palette[3] = 0x10E010_FF;
+    sprite.add_layer("a");
+    sprite.data[0].frames[0].page.copy_from_slice(&[0, 1, 2, 3]);
+
+    let mut palette = Palette::new(0x000000_FF, Some(0));
+    palette.size = 4;
+    palette[0] = 0xFF00FF_FF;
+    palette[1] = 0x0000FF_FF;
+    palette[2] = 0xFF0000_FF;
+    palette[3] = 0x00FF00_FF;
+
+    let mut opts = Convert { distance: Distance::Ciede2000, overrides: BTreeMap::new() };
+    opts.overrides.insert(3, 1);
+    convert(&mut sprite, &palette, &opts);
+
+    assert_eq!(sprite.page(0, 0).page, &[0, 2, 1, 1]);
+    assert_eq!(sprite.palette[2], 0xFF0000_FF);
+    assert_eq!(sprite.palette.transparent, Some(0));
+
+    // nothing lands on the transparent entry even if it is the closest
+    let mut opts = Convert { distance: Distance::Oklab, overrides: BTreeMap::new() };
+    let table = convert_table(&sprite, &palette, &opts);
+    assert_ne!(table[0], 0);
+    opts.overrides.insert(1, 0);
+    assert_eq!(convert_table(&sprite, &palette, &opts)[1], 0);
+}
@@ -0,0 +1,203 @@
+// ZSoft PCX. 8-bit single plane pictures with the 256 colour palette
+// after the image are read and written, 1-bit planar ones (monochrome
+// up to 16 colour EGA) are only read. Header fields are little-endian.
+
+use std::io::{self, Read, Write};
+use byteorder::{LittleEndian as LE, ByteOrder, WriteBytesExt};
+
+use draw::{Frame, Palette};
+use tool::Receiver;
+use super::{invalid, unsupported, from_rgba, to_rgba};
+
+const MANUFACTURER: u8 = 0x0A;
+const VERSION: u8 = 5;
+const RLE: u8 = 1;
+const HEADER: usize = 128;
+const PALETTE_MAGIC: u8 = 0x0C;
+const PALETTE: usize = 1 + 256 * 3;
+
+pub fn read<R: Read>(r: &mut R) -> io::Result<Receiver> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    if data.len() < HEADER || data[0] != MANUFACTURER {
+        return invalid("not a PCX file");
+    }
+
+    let encoding = data[2];
+    let bits = data[3];
+    let (x0, y0) = (LE::read_u16(&data[4..]) as usize, LE::read_u16(&data[6..]) as usize);
+    let (x1, y1) = (LE::read_u16(&data[8..]) as usize, LE::read_u16(&data[10..]) as usize);
+    let planes = data[65] as usize;
+    let bytes_per_line = LE::read_u16(&data[66..]) as usize;
+
+    if x1 < x0 || y1 < y0 {
+        return invalid("bad image size");
+    }
+    let (w, h) = (x1 - x0 + 1, y1 - y0 + 1);
+
+    match (bits, planes) {
+        (8, 1) => (),
+        (1, p) if p >= 1 && p <= 4 => (),
+        _ => return unsupported("PCX other than 8-bit or planar EGA"),
+    }
+    if bytes_per_line * 8 < w * bits as usize {
+        return invalid("bad bytes per line");
+    }
+
+    // runs may cross scanlines, so the whole image is unpacked at once
+    let line = planes * bytes_per_line;
+    let total = line * h;
+    let mut raw = Vec::with_capacity(total);
+    let mut i = HEADER;
+    while raw.len() < total && i < data.len() {
+        let b = data[i];
+        i += 1;
+        if encoding == RLE && b >= 0xC0 {
+            let c = match data.get(i) {
+                Some(&c) => c,
+                None => return invalid("truncated image"),
+            };
+            i += 1;
+            for _ in 0..b & 0x3F {
+                raw.push(c);
+            }
+        } else {
+            raw.push(b);
+        }
+    }
+    if raw.len() < total {
+        return invalid("truncated image");
+    }
+
+    let mut page = Frame::new(w, h);
+    page.transparent = None;
+    for (src, dst) in raw.chunks(line).zip(page.page.chunks_mut(w)) {
+        for (x, c) in dst.iter_mut().enumerate() {
+            *c = if bits == 8 {
+                src[x]
+            } else {
+                (0..planes).fold(0, |c, p| {
+                    c | ((src[p * bytes_per_line + x / 8] >> (7 - x % 8)) & 1) << p
+                })
+            };
+        }
+    }
+
+    let mut sprite = Receiver::new("", w, h);
+    if bits == 8 {
+        let start = data.len().saturating_sub(PALETTE);
+        if data.len() < HEADER + PALETTE || data[start] != PALETTE_MAGIC {
+            return invalid("missing 256 colour palette");
+        }
+        for (i, c) in data[start + 1..].chunks(3).enumerate() {
+            sprite.palette[i as u8] = from_rgba(c[0], c[1], c[2], 0xFF);
+        }
+        sprite.palette.size = 256;
+    } else if planes == 1 {
+        // the header palette of monochrome pictures is usually garbage
+        sprite.palette[0] = 0x000000_FF;
+        sprite.palette[1] = 0xFFFFFF_FF;
+        sprite.palette.size = 2;
+    } else {
+        for (i, c) in data[16..64].chunks(3).enumerate() {
+            sprite.palette[i as u8] = from_rgba(c[0], c[1], c[2], 0xFF);
+        }
+        sprite.palette.size = 1 << planes;
+    }
+
+    sprite.add_layer_page("load", page);
+    Ok(sprite)
+}
+
+/// RLE packs one scanline, runs never cross into the next one.
+fn pack(line: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < line.len() {
+        let c = line[i];
+        let mut n = 1;
+        while i + n < line.len() && n < 0x3F && line[i + n] == c {
+            n += 1;
+        }
+        if n > 1 || c >= 0xC0 {
+            out.push(0xC0 | n as u8);
+        }
+        out.push(c);
+        i += n;
+    }
+}
+
+/// Writes the page as a version 5, 8-bit PCX.
+pub fn write<W: Write>(w: &mut W, page: &Frame, palette: &Palette<u32>) -> io::Result<()> {
+    if page.width == 0 || page.height == 0 || page.width >= 0x10000 || page.height >= 0x10000 {
+        return unsupported("empty or oversized pictures");
+    }
+    let bytes_per_line = (page.width + 1) & !1;
+
+    let mut header = [0u8; HEADER];
+    header[..4].copy_from_slice(&[MANUFACTURER, VERSION, RLE, 8]);
+    LE::write_u16(&mut header[8..], page.width as u16 - 1);
+    LE::write_u16(&mut header[10..], page.height as u16 - 1);
+    LE::write_u16(&mut header[12..], 72);
+    LE::write_u16(&mut header[14..], 72);
+    for (i, &c) in palette.map[..16].iter().enumerate() {
+        header[16 + i * 3..19 + i * 3].copy_from_slice(&to_rgba(c)[..3]);
+    }
+    header[65] = 1;
+    LE::write_u16(&mut header[66..], bytes_per_line as u16);
+    LE::write_u16(&mut header[68..], 1); // colour palette
+    w.write_all(&header)?;
+
+    let mut body = Vec::new();
+    let mut line = vec![0; bytes_per_line];
+    for src in page.page.chunks(page.width) {
+        line[..page.width].copy_from_slice(src);
+        pack(&line, &mut body);
+    }
+    w.write_all(&body)?;
+
+    w.write_u8(PALETTE_MAGIC)?;
+    for &c in palette.iter() {
+        w.write_all(&to_rgba(c)[..3])?;
+    }
+    Ok(())
+}
+
+#[test]
+fn round_trip() {
+    let mut palette = Palette::new(0x000000_FF, None);
+    palette[1] = 0x102030_FF;
+    palette[0xC5] = 0xF0E0D0_FF;
+
+    let mut page = Frame::new(5, 2);
+    page.page.copy_from_slice(&[1, 1, 1, 0xC5, 0, 0xC5, 0xC5, 2, 3, 4]);
+
+    let mut buf = Vec::new();
+    write(&mut buf, &page, &palette).unwrap();
+    // first line: a run of three 1s, escaped 0xC5, 0 and the padding byte as a run
+    assert_eq!(&buf[HEADER..HEADER + 6], &[0xC3, 1, 0xC1, 0xC5, 0xC2, 0]);
+
+    let m = read(&mut &buf[..]).unwrap();
+    assert_eq!((m.width, m.height), (5, 2));
+    assert_eq!(m.page(0, 0).page, page.page);
+    assert_eq!(m.palette[0xC5], 0xF0E0D0_FF);
+
+    assert!(write(&mut Vec::new(), &Frame::new(0, 2), &palette).is_err());
+    assert!(write(&mut Vec::new(), &Frame::new(0x10000, 1), &palette).is_err());
+}
+
+#[test]
+fn read_planar() {
+    let mut buf = vec![0u8; HEADER];
+    buf[..4].copy_from_slice(&[MANUFACTURER, VERSION, 0, 1]);
+    LE::write_u16(&mut buf[8..], 3);
+    buf[16..22].copy_from_slice(&[0, 0, 0, 0xFF, 0, 0]);
+    buf[65] = 2;
+    LE::write_u16(&mut buf[66..], 2);
+    // plane 0 sets pixels 0 and 2, plane 1 pixels 1 and 2
+    buf.extend_from_slice(&[0b1010_0000, 0, 0b0110_0000, 0]);
+
+    let m = read(&mut &buf[..]).unwrap();
+    assert_eq!(m.page(0, 0).page, &[1, 2, 3, 0]);
+    assert_eq!(m.palette.size, 4);
+    assert_eq!(m.palette[1], 0xFF0000_FF);
+}
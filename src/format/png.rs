@@ -0,0 +1,155 @@
+use std::io::{self, Read, Write};
+
+use png::{self, HasParameters};
+
+use draw::{Frame, Palette};
+use tool::Receiver;
+use super::{to_rgba, from_rgba, color_count};
+
+/// Reads a paletted PNG keeping indices and palette order,
+/// returns `None` for true-colour images.
+pub fn read<R: Read>(r: R) -> io::Result<Option<Receiver>> {
+    let mut decoder = png::Decoder::new(r);
+    decoder.set(png::Transformations::IDENTITY);
+    let (info, mut reader) = decoder.read_info()?;
+    if info.color_type != png::ColorType::Indexed {
+        return Ok(None);
+    }
+
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data)?;
+
+    let (w, h) = (info.width as usize, info.height as usize);
+    let bits = info.bit_depth as usize;
+    let mask = ((1 << bits) - 1) as u8;
+
+    let mut page = Frame::new(w, h);
+    page.transparent = None;
+    for (line, dst) in data.chunks(info.line_size).zip(page.page.chunks_mut(w)) {
+        for (x, c) in dst.iter_mut().enumerate() {
+            let bit = x * bits;
+            let shift = 8 - bits - bit % 8;
+            *c = (line[bit / 8] >> shift) & mask;
+        }
+    }
+
+    let info = reader.info();
+    let mut sprite = Receiver::new("", w, h);
+    if let Some(ref plte) = info.palette {
+        for (i, c) in plte.chunks(3).take(256).enumerate() {
+            sprite.palette[i as u8] = from_rgba(c[0], c[1], c[2], 0xFF);
+        }
+        sprite.palette.size = (plte.len() / 3).max(1).min(256);
+    }
+    if let Some(ref trns) = info.trns {
+        for (i, &a) in trns.iter().take(256).enumerate() {
+            let c = &mut sprite.palette[i as u8];
+            *c = *c & !0xFF | a as u32;
+        }
+        page.transparent = trns.iter().position(|&a| a == 0).map(|i| i as u8);
+    }
+
+    sprite.add_layer_page("load", page);
+    Ok(Some(sprite))
+}
+
+/// Writes the page as an 8-bit paletted PNG, pixel indices are kept as is.
+pub fn write<W: Write>(w: W, page: &Frame, palette: &Palette<u32>) -> io::Result<()> {
+    let count = color_count(page, palette);
+    let mut plte = Vec::with_capacity(count * 3);
+    let mut trns = Vec::with_capacity(count);
+    for (i, &c) in palette.iter().take(count).enumerate() {
+        let [r, g, b, a] = to_rgba(c);
+        plte.extend_from_slice(&[r, g, b]);
+        trns.push(if page.transparent == Some(i as u8) { 0 } else { a });
+    }
+    while trns.last() == Some(&0xFF) {
+        trns.pop();
+    }
+
+    let mut encoder = png::Encoder::new(w, page.width as u32, page.height as u32);
+    encoder.set(png::ColorType::Indexed).set(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_chunk(png::chunk::PLTE, &plte)?;
+    if !trns.is_empty() {
+        writer.write_chunk(png::chunk::tRNS, &trns)?;
+    }
+    writer.write_image_data(&page.page)?;
+    Ok(())
+}
+
+#[test]
+fn read_low_bit_depth() {
+    let mut palette = Palette::new(0x000000_FF, None);
+    palette[1] = 0xFFFFFF_FF;
+
+    let mut page = Frame::new(3, 2);
+    page.page.copy_from_slice(&[1, 0, 1, 0, 1, 1]);
+    page.transparent = None;
+
+    // pack it by hand as 1-bit, rows are padded to a whole byte
+    let mut buf = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut buf, 3, 2);
+        encoder.set(png::ColorType::Indexed).set(png::BitDepth::One);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_chunk(png::chunk::PLTE, &[0, 0, 0, 0xFF, 0xFF, 0xFF]).unwrap();
+        writer.write_image_data(&[0b1010_0000, 0b0110_0000]).unwrap();
+    }
+
+    let sprite = read(&buf[..]).unwrap().unwrap();
+    assert_eq!(sprite.page(0, 0).page, page.page);
+    assert_eq!(sprite.page(0, 0).transparent, None);
+    assert_eq!(sprite.palette[1], palette[1]);
+    assert_eq!(sprite.palette.size, 2);
+}
+
+#[test]
+fn write_indexed() {
+    let mut palette = Palette::new(0x000000_FF, None);
+    palette[1] = 0x102030_FF;
+    palette[2] = 0x405060_FF;
+
+    let mut page = Frame::new(4, 2);
+    page.page.copy_from_slice(&[0, 1, 2, 3, 3, 2, 1, 0]);
+    page.transparent = Some(3);
+
+    let mut buf = Vec::new();
+    write(&mut buf, &page, &palette).unwrap();
+
+    let mut decoder = png::Decoder::new(&buf[..]);
+    decoder.set(png::Transformations::IDENTITY);
+    let (info, mut reader) = decoder.read_info().unwrap();
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data).unwrap();
+
+    assert_eq!(info.color_type, png::ColorType::Indexed);
+    assert_eq!(data, page.page);
+    let info = reader.info();
+    assert_eq!(&info.palette.as_ref().unwrap()[3..9], &[0x10, 0x20, 0x30, 0x40, 0x50, 0x60]);
+    assert_eq!(info.trns.as_ref().unwrap(), &[0xFF, 0xFF, 0xFF, 0x00]);
+
+    let sprite = read(&buf[..]).unwrap().unwrap();
+    assert_eq!(sprite.page(0, 0).page, page.page);
+    assert_eq!(sprite.page(0, 0).transparent, Some(3));
+    assert_eq!(sprite.palette[2], palette[2]);
+}
+
+#[test]
+fn write_indices_past_size() {
+    let mut palette = Palette::new(0x000000_FF, None);
+    palette[5] = 0x405060_FF;
+    palette.size = 2;
+
+    let mut page = Frame::new(2, 1);
+    page.page.copy_from_slice(&[0, 5]);
+    page.transparent = None;
+
+    let mut buf = Vec::new();
+    write(&mut buf, &page, &palette).unwrap();
+
+    let sprite = read(&buf[..]).unwrap().unwrap();
+    assert_eq!(sprite.page(0, 0).page, page.page);
+    assert_eq!(sprite.palette[5], 0x405060_FF);
+    assert_eq!(sprite.palette.size, 6);
+}
@@ -0,0 +1,142 @@
+use std::io::{self, Write};
+
+use png::{self, HasParameters};
+
+use math::Vector2;
+use tool::Receiver;
+use super::{Source, to_rgba};
+
+pub const MIN_SCALE: usize = 2;
+pub const MAX_SCALE: usize = 16;
+
+/// What shows through the transparent index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Background {
+    Transparent,
+    Solid(u32),
+    /// Two colours alternating in squares of `size` output pixels.
+    Checker { a: u32, b: u32, size: usize },
+}
+
+/// Cell lines, in sprite pixels, drawn over the last row and column of every cell.
+#[derive(Clone, Copy, Debug)]
+pub struct Lines {
+    pub size: Vector2<usize>,
+    pub offset: Vector2<usize>,
+    pub color: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    pub source: Source,
+    /// Nearest-neighbour factor, clamped to `MIN_SCALE..=MAX_SCALE`.
+    pub scale: usize,
+    pub grid: Option<Lines>,
+    pub background: Background,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            source: Source::Composite,
+            scale: 2,
+            grid: None,
+            background: Background::Transparent,
+        }
+    }
+}
+
+/// Source-over blending of 0xRRGGBBAA colours.
+fn blend(dst: u32, src: u32) -> u32 {
+    let [sr, sg, sb, sa] = to_rgba(src);
+    let [dr, dg, db, da] = to_rgba(dst);
+    let (sa, da) = (sa as u32, da as u32);
+    let a = sa + da * (255 - sa) / 255;
+    if a == 0 {
+        return 0;
+    }
+    let mix = |s: u8, d: u8| (s as u32 * sa + d as u32 * da * (255 - sa) / 255) / a;
+    mix(sr, dr) << 24 | mix(sg, dg) << 16 | mix(sb, db) << 8 | a
+}
+
+/// Colours of the scaled frame, row by row.
+pub fn render(sprite: &Receiver, frame: usize, opts: &Options) -> (usize, usize, Vec<u32>) {
+    let scale = opts.scale.max(MIN_SCALE).min(MAX_SCALE);
+    let page = opts.source.page(sprite, frame);
+    let (w, h) = (page.width * scale, page.height * scale);
+
+    let mut pixels = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            let c = page.page[x / scale + y / scale * page.width];
+            let c = if Some(c) != page.transparent {
+                sprite.palette[c]
+            } else {
+                match opts.background {
+                    Background::Transparent => 0,
+                    Background::Solid(c) => c,
+                    Background::Checker { a, b, size } => {
+                        let size = size.max(1);
+                        if (x / size + y / size) % 2 == 0 { a } else { b }
+                    }
+                }
+            };
+            pixels.push(c);
+        }
+    }
+
+    if let Some(grid) = opts.grid {
+        let (cw, ch) = (grid.size.x.max(1) * scale, grid.size.y.max(1) * scale);
+        let (ox, oy) = (grid.offset.x * scale % cw, grid.offset.y * scale % ch);
+        for y in 0..h {
+            for x in 0..w {
+                if (x + 1 + cw - ox) % cw == 0 || (y + 1 + ch - oy) % ch == 0 {
+                    let c = &mut pixels[x + y * w];
+                    *c = blend(*c, grid.color);
+                }
+            }
+        }
+    }
+
+    (w, h, pixels)
+}
+
+/// Writes the current frame as a true-colour PNG.
+pub fn write<W: Write>(w: W, sprite: &Receiver, opts: &Options) -> io::Result<()> {
+    let (width, height, pixels) = render(sprite, sprite.frame, opts);
+    let data: Vec<u8> = pixels.iter().flat_map(|&c| to_rgba(c).to_vec()).collect();
+
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    Ok(())
+}
+
+#[test]
+fn scaled_with_grid() {
+    let mut sprite = Receiver::new("", 2, 1);
+    sprite.palette[1] = 0x102030_FF;
+    sprite.add_layer("a");
+    sprite.data[0].frames[0].page.copy_from_slice(&[0, 1]);
+
+    let opts = Options {
+        scale: 2,
+        background: Background::Checker { a: 0xFFFFFF_FF, b: 0x000000_FF, size: 1 },
+        .. Options::default()
+    };
+    let (w, h, pixels) = render(&sprite, 0, &opts);
+    assert_eq!((w, h), (4, 2));
+    assert_eq!(&pixels[..4], &[0xFFFFFF_FF, 0x000000_FF, 0x102030_FF, 0x102030_FF]);
+    assert_eq!(&pixels[4..], &[0x000000_FF, 0xFFFFFF_FF, 0x102030_FF, 0x102030_FF]);
+
+    // one pixel cells, the line is the right and bottom edge of each
+    let opts = Options {
+        grid: Some(Lines { size: Vector2::new(1, 1), offset: Vector2::new(0, 0), color: 0xFF0000_FF }),
+        background: Background::Transparent,
+        .. opts
+    };
+    let (_, _, pixels) = render(&sprite, 0, &opts);
+    assert_eq!(&pixels[..4], &[0, 0xFF0000_FF, 0x102030_FF, 0xFF0000_FF]);
+    assert_eq!(&pixels[4..], &[0xFF0000_FF; 4]);
+}
@@ -0,0 +1,144 @@
+// Colour reduction for palette extraction. Works on the unique colours
+// of an image with their pixel counts, alpha is ignored.
+
+use std::collections::HashMap;
+
+use super::{to_rgba, from_rgba, nearest};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quantizer {
+    /// Splits the colour box with the widest spread at its median until there are enough.
+    MedianCut,
+    /// Median cut refined by a few rounds of k-means, slower but closer to the source.
+    KMeans,
+}
+
+const KMEANS_ROUNDS: usize = 8;
+
+/// Unique opaque colours in order of first appearance, with how often they occur.
+/// Fully transparent pixels are skipped.
+pub fn histogram(pixels: &[u32]) -> Vec<(u32, usize)> {
+    let mut index = HashMap::new();
+    let mut colors: Vec<(u32, usize)> = Vec::new();
+    for &c in pixels.iter().filter(|&&c| c & 0xFF != 0) {
+        let c = c | 0xFF;
+        let i = *index.entry(c).or_insert(colors.len());
+        if i == colors.len() {
+            colors.push((c, 0));
+        }
+        colors[i].1 += 1;
+    }
+    colors
+}
+
+fn rgb(c: u32) -> [i32; 3] {
+    let [r, g, b, _] = to_rgba(c);
+    [r as i32, g as i32, b as i32]
+}
+
+fn mean(colors: &[(u32, usize)]) -> u32 {
+    let mut sum = [0usize; 3];
+    let mut total = 0;
+    for &(c, n) in colors {
+        for (s, v) in sum.iter_mut().zip(&rgb(c)) {
+            *s += *v as usize * n;
+        }
+        total += n;
+    }
+    let total = total.max(1);
+    let avg = |s: usize| ((s + total / 2) / total) as u8;
+    from_rgba(avg(sum[0]), avg(sum[1]), avg(sum[2]), 0xFF)
+}
+
+/// Widest channel and its spread.
+fn spread(colors: &[(u32, usize)]) -> (usize, i32) {
+    let mut min = [255; 3];
+    let mut max = [0; 3];
+    for &(c, _) in colors {
+        let c = rgb(c);
+        for i in 0..3 {
+            min[i] = min[i].min(c[i]);
+            max[i] = max[i].max(c[i]);
+        }
+    }
+    (0..3).map(|i| (i, max[i] - min[i])).max_by_key(|&(_, d)| d).unwrap()
+}
+
+fn median_cut(colors: &[(u32, usize)], count: usize) -> Vec<Vec<(u32, usize)>> {
+    let mut boxes = vec![colors.to_vec()];
+    while boxes.len() < count {
+        // the box with the widest spread among those that can still be split
+        let next = boxes.iter().enumerate()
+            .filter(|&(_, b)| b.len() > 1)
+            .map(|(i, b)| (i, spread(b)))
+            .max_by_key(|&(_, (_, d))| d);
+        let (i, axis) = match next {
+            Some((i, (axis, _))) => (i, axis),
+            None => break,
+        };
+
+        let mut b = boxes.swap_remove(i);
+        b.sort_by_key(|&(c, _)| rgb(c)[axis]);
+        let total: usize = b.iter().map(|&(_, n)| n).sum();
+        let mut acc = 0;
+        let mut split = 1;
+        for (j, &(_, n)) in b.iter().enumerate() {
+            acc += n;
+            if acc * 2 >= total {
+                split = (j + 1).max(1).min(b.len() - 1);
+                break;
+            }
+        }
+        let rest = b.split_off(split);
+        boxes.push(b);
+        boxes.push(rest);
+    }
+    boxes
+}
+
+/// At most `count` colours representing the histogram.
+pub fn reduce(colors: &[(u32, usize)], count: usize, quantizer: Quantizer) -> Vec<u32> {
+    let count = count.max(1);
+    if colors.len() <= count {
+        return colors.iter().map(|&(c, _)| c).collect();
+    }
+
+    let mut centers: Vec<u32> = median_cut(colors, count).iter().map(|b| mean(b)).collect();
+    if quantizer == Quantizer::KMeans {
+        for _ in 0..KMEANS_ROUNDS {
+            let mut clusters = vec![Vec::new(); centers.len()];
+            for &(c, n) in colors {
+                clusters[nearest(&centers, c)].push((c, n));
+            }
+            let next: Vec<u32> = clusters.iter().zip(&centers)
+                .map(|(cluster, &center)| if cluster.is_empty() { center } else { mean(cluster) })
+                .collect();
+            if next == centers {
+                break;
+            }
+            centers = next;
+        }
+    }
+
+    centers.sort();
+    centers.dedup();
+    centers
+}
+
+#[test]
+fn reduce_clusters() {
+    let pixels = [
+        0xFF0000_FF, 0xF00000_FF, 0xFF0000_FF,
+        0x0000FF_FF, 0x0000F0_FF,
+        0x000000_00,
+    ];
+    let colors = histogram(&pixels);
+    assert_eq!(colors, &[(0xFF0000_FF, 2), (0xF00000_FF, 1), (0x0000FF_FF, 1), (0x0000F0_FF, 1)]);
+
+    for &q in &[Quantizer::MedianCut, Quantizer::KMeans] {
+        let mut reduced = reduce(&colors, 2, q);
+        reduced.sort();
+        assert_eq!(reduced, &[0x0000F8_FF, 0xFA0000_FF], "{:?}", q);
+    }
+    assert_eq!(reduce(&colors, 8, Quantizer::MedianCut).len(), 4);
+}
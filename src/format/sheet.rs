@@ -0,0 +1,291 @@
+use std::io::{self, Write};
+
+use math::{Rect, Vector2};
+use draw::Frame;
+use tool::{Receiver, Layer};
+use super::Source;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Row,
+    Column,
+    /// Fixed number of columns, zero picks a roughly square grid.
+    Grid(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    pub source: Source,
+    pub layout: Layout,
+    /// Empty pixels between cells.
+    pub padding: usize,
+    /// Edge pixels repeated around every frame against bleeding when filtered.
+    pub extrude: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            source: Source::Composite,
+            layout: Layout::Grid(0),
+            padding: 0,
+            extrude: 0,
+        }
+    }
+}
+
+pub struct Sheet {
+    pub page: Frame,
+    /// Frame rectangles on the sheet, without the extruded border.
+    pub rects: Vec<Rect<usize>>,
+    pub durations: Vec<u32>,
+}
+
+impl Layout {
+    /// Columns and rows for `count` cells.
+    pub fn cells(self, count: usize) -> (usize, usize) {
+        let count = count.max(1);
+        let columns = match self {
+            Layout::Row => count,
+            Layout::Column => 1,
+            Layout::Grid(0) => (1..count + 1).find(|&c| c * c >= count).unwrap(),
+            Layout::Grid(n) => n.min(count),
+        };
+        (columns, (count + columns - 1) / columns)
+    }
+}
+
+/// Packs every frame of the source into one page.
+pub fn pack(sprite: &Receiver, opts: &Options) -> Sheet {
+    let count = sprite.frame_count();
+    let (w, h) = (sprite.width, sprite.height);
+    if w == 0 || h == 0 {
+        return Sheet { page: Frame::new(0, 0), rects: Vec::new(), durations: Vec::new() };
+    }
+    let e = opts.extrude;
+    let (columns, rows) = opts.layout.cells(count);
+
+    let cell_w = w + 2 * e + opts.padding;
+    let cell_h = h + 2 * e + opts.padding;
+
+    let pages: Vec<_> = (0..count).map(|i| opts.source.page(sprite, i)).collect();
+    let transparent = pages.iter().filter_map(|page| page.transparent).next();
+
+    let mut page = Frame::new(cell_w * columns - opts.padding, cell_h * rows - opts.padding);
+    page.transparent = transparent;
+    let fill = transparent.unwrap_or(0);
+    for c in &mut page.page {
+        *c = fill;
+    }
+
+    let mut rects = Vec::with_capacity(count);
+    for (i, src) in pages.iter().enumerate() {
+        let x = (i % columns) * cell_w + e;
+        let y = (i / columns) * cell_h + e;
+
+        // clamping the source coordinates repeats the edges into the border
+        for dy in 0..h + 2 * e {
+            let sy = (dy as isize - e as isize).max(0).min(h as isize - 1) as usize;
+            for dx in 0..w + 2 * e {
+                let sx = (dx as isize - e as isize).max(0).min(w as isize - 1) as usize;
+                let dst = (x + dx - e) + (y + dy - e) * page.width;
+                page.page[dst] = src.page[sx + sy * w];
+            }
+        }
+
+        rects.push(Rect::from_coords_and_size(x, y, w, h));
+    }
+
+    let durations = (0..count).map(|i| sprite.duration(i)).collect();
+    Sheet { page, rects, durations }
+}
+
+fn escape(s: &str) -> String {
+    s.chars().flat_map(|c| match c {
+        '"' => vec!['\\', '"'],
+        '\\' => vec!['\\', '\\'],
+        c => vec![c],
+    }).collect()
+}
+
+/// Frame rectangles and durations in the common JSON sheet layout.
+pub fn write_json<W: Write>(w: &mut W, sheet: &Sheet, image: &str) -> io::Result<()> {
+    writeln!(w, "{{")?;
+    writeln!(w, "  \"frames\": [")?;
+    for (i, (r, duration)) in sheet.rects.iter().zip(&sheet.durations).enumerate() {
+        let comma = if i + 1 < sheet.rects.len() { "," } else { "" };
+        writeln!(w, "    {{ \"frame\": {{ \"x\": {}, \"y\": {}, \"w\": {}, \"h\": {} }}, \"duration\": {} }}{}",
+            r.min.x, r.min.y, r.dx(), r.dy(), duration, comma)?;
+    }
+    writeln!(w, "  ],")?;
+    writeln!(w, "  \"meta\": {{")?;
+    writeln!(w, "    \"image\": \"{}\",", escape(image))?;
+    writeln!(w, "    \"size\": {{ \"w\": {}, \"h\": {} }}", sheet.page.width, sheet.page.height)?;
+    writeln!(w, "  }}")?;
+    writeln!(w, "}}")
+}
+
+/// Frame rectangles as Amethyst's `SpriteSheetFormat` RON.
+pub fn write_ron<W: Write>(w: &mut W, sheet: &Sheet) -> io::Result<()> {
+    writeln!(w, "(")?;
+    writeln!(w, "    spritesheet_width: {:.1},", sheet.page.width as f32)?;
+    writeln!(w, "    spritesheet_height: {:.1},", sheet.page.height as f32)?;
+    writeln!(w, "    sprites: [")?;
+    for r in &sheet.rects {
+        writeln!(w, "        (x: {:.1}, y: {:.1}, width: {:.1}, height: {:.1}),",
+            r.min.x as f32, r.min.y as f32, r.dx() as f32, r.dy() as f32)?;
+    }
+    writeln!(w, "    ],")?;
+    writeln!(w, ")")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    RowMajor,
+    ColumnMajor,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Slice {
+    pub size: Vector2<usize>,
+    pub offset: Vector2<usize>,
+    pub order: Order,
+    /// Drop cells holding nothing but the transparent colour.
+    pub skip_empty: bool,
+}
+
+impl Default for Slice {
+    fn default() -> Self {
+        Self {
+            size: Vector2::new(16, 16),
+            offset: Vector2::new(0, 0),
+            order: Order::RowMajor,
+            skip_empty: true,
+        }
+    }
+}
+
+/// Cuts the first frame of the composite into cells,
+/// they become consecutive frames of a single layer.
+pub fn slice(sprite: &Receiver, opts: &Slice) -> Receiver {
+    let (w, h) = (opts.size.x.max(1), opts.size.y.max(1));
+    let src = sprite.composite(0);
+
+    let columns = sprite.width.saturating_sub(opts.offset.x) / w;
+    let rows = sprite.height.saturating_sub(opts.offset.y) / h;
+
+    let cells: Vec<(usize, usize)> = match opts.order {
+        Order::RowMajor => (0..rows).flat_map(|y| (0..columns).map(move |x| (x, y))).collect(),
+        Order::ColumnMajor => (0..columns).flat_map(|x| (0..rows).map(move |y| (x, y))).collect(),
+    };
+
+    let mut m = Receiver::new(&sprite.name, w, h);
+    m.palette.map = sprite.palette.map;
+    m.palette.size = sprite.palette.size;
+    m.palette.transparent = sprite.palette.transparent;
+    m.cycles = sprite.cycles.clone();
+
+    let mut layer = Layer::new("sheet");
+    for (cx, cy) in cells {
+        let mut page = Frame::new(w, h);
+        page.transparent = src.transparent;
+        let (ox, oy) = (opts.offset.x + cx * w, opts.offset.y + cy * h);
+        for y in 0..h {
+            let start = ox + (oy + y) * src.width;
+            page.page[y * w..y * w + w].copy_from_slice(&src.page[start..start + w]);
+        }
+
+        let empty = page.page.iter().all(|&c| Some(c) == page.transparent);
+        if !(opts.skip_empty && empty) {
+            layer.push(page);
+        }
+    }
+
+    if layer.frames.is_empty() {
+        layer.push(Frame::new(w, h));
+    }
+    m.data.push(layer);
+    m
+}
+
+#[test]
+fn pack_grid() {
+    let mut sprite = Receiver::new("", 2, 2);
+    sprite.add_layer("a");
+    sprite.data[0].frames[0].page.copy_from_slice(&[1, 2, 3, 4]);
+    for i in 1..3 {
+        let mut page = sprite.page(0, 0).clone();
+        for c in &mut page.page {
+            *c += 4 * i as u8;
+        }
+        sprite.data[0].push(page);
+    }
+    sprite.set_duration(2, 30);
+
+    let opts = Options { layout: Layout::Grid(2), padding: 1, extrude: 1, .. Options::default() };
+    let sheet = pack(&sprite, &opts);
+
+    assert_eq!((sheet.page.width, sheet.page.height), (9, 9));
+    assert_eq!(sheet.rects[1], Rect::from_coords_and_size(6, 1, 2, 2));
+    assert_eq!(sheet.rects[2], Rect::from_coords_and_size(1, 6, 2, 2));
+    assert_eq!(sheet.durations, &[100, 100, 30]);
+
+    let row = |y: usize| &sheet.page.page[y * 9..y * 9 + 9];
+    assert_eq!(row(0), &[1, 1, 2, 2, 0, 5, 5, 6, 6]);
+    assert_eq!(row(2), &[3, 3, 4, 4, 0, 7, 7, 8, 8]);
+    assert_eq!(row(4), &[0; 9]);
+    assert_eq!(row(8), &[11, 11, 12, 12, 0, 0, 0, 0, 0]);
+
+    let mut ron = Vec::new();
+    write_ron(&mut ron, &sheet).unwrap();
+    let ron = String::from_utf8(ron).unwrap();
+    assert!(ron.contains("(x: 6.0, y: 1.0, width: 2.0, height: 2.0),"));
+}
+
+#[test]
+fn pack_empty() {
+    let mut sprite = Receiver::new("", 0, 0);
+    sprite.add_layer("a");
+    let opts = Options { extrude: 1, padding: 1, .. Options::default() };
+    let sheet = pack(&sprite, &opts);
+    assert_eq!((sheet.page.width, sheet.page.height), (0, 0));
+    assert!(sheet.rects.is_empty());
+}
+
+#[test]
+fn layout_cells() {
+    assert_eq!(Layout::Row.cells(5), (5, 1));
+    assert_eq!(Layout::Column.cells(5), (1, 5));
+    assert_eq!(Layout::Grid(0).cells(5), (3, 2));
+    assert_eq!(Layout::Grid(0).cells(9), (3, 3));
+    assert_eq!(Layout::Grid(4).cells(2), (2, 1));
+}
+
+#[test]
+fn slice_cells() {
+    let mut sprite = Receiver::new("", 5, 4);
+    sprite.add_layer("a");
+    // 2x2 cells after a 1 pixel offset, the top right one is empty
+    sprite.data[0].frames[0].page.copy_from_slice(&[
+        9, 9, 9, 9, 9,
+        9, 1, 1, 0, 0,
+        9, 1, 1, 0, 0,
+        9, 2, 2, 3, 3,
+    ]);
+
+    let opts = Slice {
+        size: Vector2::new(2, 2),
+        offset: Vector2::new(1, 1),
+        .. Slice::default()
+    };
+    let m = slice(&sprite, &opts);
+    assert_eq!((m.width, m.height), (2, 2));
+    assert_eq!(m.frame_count(), 1);
+    assert_eq!(m.page(0, 0).page, &[1, 1, 1, 1]);
+
+    let opts = Slice { offset: Vector2::new(1, 0), order: Order::ColumnMajor, skip_empty: false, .. opts };
+    let m = slice(&sprite, &opts);
+    assert_eq!(m.frame_count(), 4);
+    assert_eq!(m.page(0, 1).page, &[1, 1, 2, 2]);
+    assert_eq!(m.page(0, 2).page, &[9, 9, 0, 0]);
+}
@@ -0,0 +1,121 @@
+use std::io::{self, Write};
+
+use math::Vector2;
+use tool::{Receiver, Property};
+use super::to_rgba;
+
+/// How the sprite is cut into tiles.
+#[derive(Clone, Copy, Debug)]
+pub struct Tileset {
+    pub size: Vector2<usize>,
+    /// Pixels around the tiles, Tiled has one value for both axes.
+    pub margin: usize,
+    /// Pixels between neighbouring tiles.
+    pub spacing: usize,
+}
+
+impl Tileset {
+    /// Tiles of an editor grid: the offset becomes the margin and cells touch.
+    /// Tiled has a single margin, so offsets that differ per axis are refused.
+    pub fn from_grid(size: Vector2<usize>, offset: Vector2<usize>) -> io::Result<Self> {
+        if offset.x != offset.y {
+            let msg = "tilesets need the same grid offset on both axes";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        Ok(Self { size, margin: offset.x, spacing: 0 })
+    }
+
+    /// Columns and rows of whole tiles in an image of `width` x `height`.
+    pub fn cells(&self, width: usize, height: usize) -> (usize, usize) {
+        let count = |len: usize, tile: usize| {
+            let (len, step) = (len.saturating_sub(2 * self.margin), tile.max(1) + self.spacing);
+            (len + self.spacing) / step
+        };
+        (count(width, self.size.x), count(height, self.size.y))
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.chars().map(|c| match c {
+        '&' => "&amp;".to_string(),
+        '<' => "&lt;".to_string(),
+        '>' => "&gt;".to_string(),
+        '"' => "&quot;".to_string(),
+        c => c.to_string(),
+    }).collect()
+}
+
+/// Writes a `.tsx` tileset for the sprite saved as `image`, with the
+/// transparent colour and the properties of every tile.
+pub fn write_tsx<W: Write>(w: &mut W, sprite: &Receiver, tileset: &Tileset, image: &str) -> io::Result<()> {
+    let (columns, rows) = tileset.cells(sprite.width, sprite.height);
+    let name = if sprite.name.is_empty() { image } else { &sprite.name };
+
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(w, r#"<tileset version="1.2" name="{}" tilewidth="{}" tileheight="{}" spacing="{}" margin="{}" tilecount="{}" columns="{}">"#,
+        escape(name), tileset.size.x, tileset.size.y, tileset.spacing, tileset.margin, columns * rows, columns)?;
+
+    write!(w, r#" <image source="{}""#, escape(image))?;
+    if let Some(t) = sprite.palette.transparent {
+        let [r, g, b, _] = to_rgba(sprite.palette[t]);
+        write!(w, r#" trans="{:02x}{:02x}{:02x}""#, r, g, b)?;
+    }
+    writeln!(w, r#" width="{}" height="{}"/>"#, sprite.width, sprite.height)?;
+
+    for (&id, props) in sprite.tiles.iter().filter(|&(&id, props)| id < columns * rows && !props.is_empty()) {
+        writeln!(w, r#" <tile id="{}">"#, id)?;
+        writeln!(w, "  <properties>")?;
+        for (name, value) in props {
+            let (kind, value) = match *value {
+                Property::Bool(v) => ("bool", v.to_string()),
+                Property::Int(v) => ("int", v.to_string()),
+                Property::Str(ref v) => ("string", escape(v)),
+            };
+            writeln!(w, r#"   <property name="{}" type="{}" value="{}"/>"#, escape(name), kind, value)?;
+        }
+        writeln!(w, "  </properties>")?;
+        writeln!(w, " </tile>")?;
+    }
+    writeln!(w, "</tileset>")
+}
+
+#[test]
+fn tileset_from_grid() {
+    let tileset = Tileset::from_grid(Vector2::new(16, 8), Vector2::new(2, 2)).unwrap();
+    assert_eq!((tileset.margin, tileset.spacing), (2, 0));
+    assert!(Tileset::from_grid(Vector2::new(16, 8), Vector2::new(2, 0)).is_err());
+}
+
+#[test]
+fn tileset_cells() {
+    let tileset = Tileset { size: Vector2::new(16, 8), margin: 1, spacing: 2 };
+    // 1 + 16 + 2 + 16 + 2 + 16 + 1 = 54
+    assert_eq!(tileset.cells(54, 20), (3, 2));
+    assert_eq!(tileset.cells(53, 9), (2, 0));
+}
+
+#[test]
+fn tsx_properties() {
+    use tool::Properties;
+
+    let mut sprite = Receiver::new("walls", 32, 16);
+    sprite.palette[3] = 0xFF00FF_FF;
+    sprite.palette.transparent = Some(3);
+    let mut props = Properties::new();
+    props.insert("collision".to_string(), Property::Bool(true));
+    props.insert("name".to_string(), Property::Str("a<b".to_string()));
+    sprite.tiles.insert(1, props);
+    sprite.tiles.insert(7, Properties::new());
+
+    let tileset = Tileset { size: Vector2::new(16, 16), margin: 0, spacing: 0 };
+    let mut buf = Vec::new();
+    write_tsx(&mut buf, &sprite, &tileset, "walls.png").unwrap();
+    let tsx = String::from_utf8(buf).unwrap();
+
+    assert!(tsx.contains(r#"tilewidth="16" tileheight="16" spacing="0" margin="0" tilecount="2" columns="2""#));
+    assert!(tsx.contains(r#"<image source="walls.png" trans="ff00ff" width="32" height="16"/>"#));
+    assert!(tsx.contains(r#"<tile id="1">"#));
+    assert!(tsx.contains(r#"<property name="collision" type="bool" value="true"/>"#));
+    assert!(tsx.contains(r#"value="a&lt;b""#));
+    assert!(!tsx.contains(r#"<tile id="7">"#));
+}
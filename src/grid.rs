@@ -10,7 +10,20 @@ pub struct Grid {
 }
 
 impl Grid {
-    pub fn paint(&self, ctx: &mut Canvas, zoom: i16, rect: Rect<i32>) {
+    /// Cell size and the offset wrapped into the first cell, both positive.
+    pub fn cells(&self) -> (Vector2<usize>, Vector2<usize>) {
+        let size = Vector2::new(self.size.x.max(1), self.size.y.max(1));
+        let wrap = |o: i16, s: i16| ((o % s + s) % s) as usize;
+        let offset = Vector2::new(wrap(self.offset.x, size.x), wrap(self.offset.y, size.y));
+        (Vector2::new(size.x as usize, size.y as usize), offset)
+    }
+
+    /// Draws the cell lines and the canvas border. `overlay` gives the
+    /// colour that stands out against a sprite pixel, lines change colour
+    /// along with the pixels under them.
+    pub fn paint<F>(&self, ctx: &mut Canvas, zoom: i16, rect: Rect<i32>, overlay: F)
+        where F: Fn(i32, i32) -> u32
+    {
         if !self.visible {
             return;
         }
@@ -22,9 +35,6 @@ impl Grid {
             (pos, size)
         };
 
-        let grid_color = GRID_COLOR.to_be();
-        let corner_color = CORNER_COLOR.to_be();
-
         let (ox, oy) = (pos.x, pos.y);
 
         let (x1, x2) = (ox, ox + size.x * zoom);
@@ -39,21 +49,50 @@ impl Grid {
         let zx = self.size.x * zoom;
         let zy = self.size.y * zoom;
 
-        //ctx.clip(Rect::from_min_dim(pos, size * zoom));
-        for x in 1..ex + 1 {
-            let x = ox + x * zx;
-            ctx.vline(x - 1, y1, y2, grid_color);
+        // screen position of each line and how far it reaches past the canvas,
+        // the border goes around the corners
+        let mut vlines: Vec<(i16, i16)> = (1..ex + 1).map(|x| (ox + x * zx - 1, 0)).collect();
+        let mut hlines: Vec<(i16, i16)> = (1..ey + 1).map(|y| (oy + y * zy - 1, 0)).collect();
+        vlines.push((x1 - 1, 1));
+        vlines.push((x2, 1));
+        hlines.push((y1 - 1, 1));
+        hlines.push((y2, 1));
+
+        for (x, pad) in vlines {
+            let sx = ((x - pos.x) / zoom) as i32;
+            runs(size.y, |sy| overlay(sx, sy as i32), |a, b, color| {
+                let a = if a == 0 { y1 - pad } else { y1 + a * zoom };
+                let b = if b == size.y { y2 - 1 + pad } else { y1 + b * zoom - 1 };
+                ctx.vline(x, a, b, color.to_be());
+            });
         }
-        for y in 1..ey + 1 {
-            let y = oy + y * zy;
-            ctx.hline(x1, x2, y - 1, grid_color);
+        for (y, pad) in hlines {
+            let sy = ((y - pos.y) / zoom) as i32;
+            runs(size.x, |sx| overlay(sx as i32, sy), |a, b, color| {
+                let a = if a == 0 { x1 - pad } else { x1 + a * zoom };
+                let b = if b == size.x { x2 - 1 + pad } else { x1 + b * zoom - 1 };
+                ctx.hline(a, b, y, color.to_be());
+            });
         }
-        //ctx.unclip();
+    }
+}
 
-        // canvas border
-        ctx.hline(x1-1, x2, y1-1, corner_color);
-        ctx.hline(x1-1, x2, y2+0, corner_color);
-        ctx.vline(x1-1, y1-1, y2, corner_color);
-        ctx.vline(x2+0, y1-1, y2, corner_color);
+/// Splits `0..len` into runs of one colour, `draw` gets start, end and colour.
+fn runs<C, D>(len: i16, color: C, mut draw: D)
+    where C: Fn(i16) -> u32, D: FnMut(i16, i16, u32)
+{
+    if len <= 0 {
+        return;
+    }
+    let (mut start, mut c) = (0, color(0));
+    for i in 1..len + 1 {
+        let next = if i < len { Some(color(i)) } else { None };
+        if next != Some(c) {
+            draw(start, i, c);
+            start = i;
+            if let Some(next) = next {
+                c = next;
+            }
+        }
     }
 }
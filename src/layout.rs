@@ -124,6 +124,32 @@ impl<'a, 'state> EditorLayout<'a, 'state> {
         self.update |= *angle == start;
     }
 
+    /// Horizontal slider over `min..max`, true when the value moved.
+    pub fn slider(&mut self, label: &str, v: &mut f32, min: f32, max: f32) -> bool {
+        let ctx = self.line_prop(label);
+
+        let mut slider = SliderModel { min, max, current: *v };
+
+        let rect = ctx.rect();
+        let pad = (rect.dy() - 2.0) / 2.0;
+        ctx.quad(rgba(0xAAAAAA_AA), rect.pad_y(pad));
+
+        HSLIDER.behavior(&ctx, &mut self.state, &mut slider);
+
+        let update = slider.current != *v;
+        *v = slider.current;
+        self.update |= update;
+        update
+    }
+
+    /// Text value that reacts to clicks, true when clicked.
+    pub fn text_button(&mut self, label: &str, text: &str) -> bool {
+        let ctx = self.line_prop(label);
+        let clicked = BTN.behavior(&ctx, &mut self.state, &mut ());
+        ctx.label(0.0, 0.5, WHITE, text);
+        clicked
+    }
+
     pub fn num_base<T, F>(&mut self, label: &str, sub: &str, v: &mut T, filter: F) -> bool
         where T: BaseNumExt + ToString, F: FnOnce(bool, &mut T) + Copy
     {
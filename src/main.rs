@@ -7,8 +7,16 @@
 #![feature(decl_macro)]
 
 extern crate rand;
+extern crate byteorder;
+extern crate flate2;
+extern crate toml;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 extern crate redo;
 extern crate image;
+extern crate png;
+extern crate gif;
 extern crate nfd;
 extern crate cgmath;
 #[macro_use]
@@ -55,6 +63,11 @@ mod layout;
 mod prev;
 
 mod open;
+mod format;
+mod cli;
+mod pipeline;
+mod recent;
+mod autosave;
 mod render;
 
 const SCREEN_TITLE: &str = "rust-sdl2_gfx: draw line & FPSManager";
@@ -62,6 +75,12 @@ pub const SCREEN_WIDTH: u32 = 1024;
 pub const SCREEN_HEIGHT: u32 = 720;
 
 fn main() {
+    // any argument means batch mode, SDL is never touched there
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
+
     let r = render::Canvas::new(SCREEN_TITLE, SCREEN_WIDTH, SCREEN_HEIGHT);
 
     let mut world = World::new();
@@ -1,80 +1,222 @@
 #![allow(dead_code)]
 
+use std::io;
 use std::path::Path;
 
 use draw::*;
 use tool::Receiver;
-use image::math::nq::NeuQuant as NQ;
-
-fn get_pal(nq: &NQ) -> Vec<u32> {
-    #[derive(Clone, Copy)]
-    struct Quad<T> {
-        r: T,
-        g: T,
-        b: T,
-        a: T,
+use format::{self, native, Source, Error};
+
+fn extension<P: AsRef<Path>>(filename: P) -> String {
+    filename.as_ref().extension()
+        .and_then(|s| s.to_str())
+        .map_or("".to_string(), |s| s.to_ascii_lowercase())
+}
+
+pub fn is_native<P: AsRef<Path>>(filename: P) -> bool {
+    extension(filename) == native::EXTENSION
+}
+
+pub fn save_sprite<P: AsRef<Path>>(filename: P, sprite: &Receiver) -> io::Result<()> {
+    use std::fs::File;
+    use std::io::BufWriter;
+
+    let mut w = BufWriter::new(File::create(filename)?);
+    native::write(&mut w, sprite)
+}
+
+/// `repeat` only matters for GIF, other formats get a single frame.
+pub fn export_sprite<P: AsRef<Path>>(filename: P, sprite: &Receiver, source: Source, repeat: format::gif::Loop) -> io::Result<()> {
+    use std::fs::File;
+    use std::io::BufWriter;
+
+    match &*extension(&filename) {
+        "png" => {
+            let w = BufWriter::new(File::create(filename)?);
+            let page = source.page(sprite, sprite.frame);
+            format::png::write(w, &page, &sprite.palette)
+        }
+        "gif" => {
+            let w = BufWriter::new(File::create(filename)?);
+            let opts = format::gif::Options { source, repeat, .. Default::default() };
+            format::gif::write(w, sprite, &opts)
+        }
+        "ase" | "aseprite" => {
+            let mut w = BufWriter::new(File::create(filename)?);
+            format::aseprite::write(&mut w, sprite)
+        }
+        "bmp" => {
+            let mut w = BufWriter::new(File::create(filename)?);
+            let page = source.page(sprite, sprite.frame);
+            format::bmp::write(&mut w, &page, &sprite.palette)
+        }
+        "pcx" => {
+            let mut w = BufWriter::new(File::create(filename)?);
+            let page = source.page(sprite, sprite.frame);
+            format::pcx::write(&mut w, &page, &sprite.palette)
+        }
+        ext @ "iff" | ext @ "ilbm" | ext @ "lbm" | ext @ "bbm" => {
+            let kind = format::ilbm::Kind::from_extension(ext).unwrap();
+            let mut w = BufWriter::new(File::create(filename)?);
+            let page = source.page(sprite, sprite.frame);
+            format::ilbm::write(&mut w, &page, &sprite.palette, &sprite.cycles, kind)
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported export format")),
     }
+}
+
+/// Writes the scaled frame as a true-colour PNG.
+pub fn export_preview<P: AsRef<Path>>(filename: P, sprite: &Receiver, opts: &format::preview::Options) -> io::Result<()> {
+    use std::fs::File;
+    use std::io::BufWriter;
+
+    let w = BufWriter::new(File::create(filename)?);
+    format::preview::write(w, sprite, opts)
+}
+
+/// Writes the composite as a paletted PNG with a Tiled `.tsx` tileset next to it.
+pub fn export_tileset<P: AsRef<Path>>(filename: P, sprite: &Receiver, tileset: &format::tiled::Tileset) -> io::Result<()> {
+    use std::fs::File;
+    use std::io::BufWriter;
+
+    let filename = filename.as_ref();
+    let w = BufWriter::new(File::create(filename)?);
+    format::png::write(w, &sprite.composite(sprite.frame), &sprite.palette)?;
+
+    let image = filename.file_name().unwrap().to_string_lossy();
+    let mut w = BufWriter::new(File::create(filename.with_extension("tsx"))?);
+    format::tiled::write_tsx(&mut w, sprite, tileset, &image)
+}
 
-    type Neuron = Quad<f64>;
-    type Color = Quad<i32>;
-
-    /// Neural network based color quantizer.
-    pub struct NeuQuant {
-        network: Vec<Neuron>,
-        colormap: Vec<Color>,
-        netindex: Vec<usize>,
-        bias: Vec<f64>, // bias and freq arrays for learning
-        freq: Vec<f64>,
-        samplefac: i32,
-        netsize: usize,
+/// Writes the sheet as a paletted PNG with `.json` and `.ron` metadata next to it.
+pub fn export_sheet<P: AsRef<Path>>(filename: P, sprite: &Receiver, opts: &format::sheet::Options) -> io::Result<()> {
+    use std::fs::File;
+    use std::io::BufWriter;
+
+    let filename = filename.as_ref();
+    let sheet = format::sheet::pack(sprite, opts);
+
+    let w = BufWriter::new(File::create(filename)?);
+    format::png::write(w, &sheet.page, &sprite.palette)?;
+
+    let image = filename.file_name().unwrap().to_string_lossy();
+    let mut w = BufWriter::new(File::create(filename.with_extension("json"))?);
+    format::sheet::write_json(&mut w, &sheet, &image)?;
+
+    let mut w = BufWriter::new(File::create(filename.with_extension("ron"))?);
+    format::sheet::write_ron(&mut w, &sheet)
+}
+
+fn palette_kind<P: AsRef<Path>>(filename: P) -> io::Result<format::palette::Kind> {
+    let ext = extension(filename);
+    format::palette::Kind::from_extension(&ext)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!(".{} palettes", ext)))
+}
+
+pub fn is_palette<P: AsRef<Path>>(filename: P) -> bool {
+    palette_kind(filename).is_ok()
+}
+
+pub fn load_palette<P: AsRef<Path>>(filename: P) -> Result<Palette<u32>, Error> {
+    use std::fs::File;
+
+    let kind = palette_kind(&filename)?;
+    format::palette::read(File::open(filename)?, kind)
+}
+
+/// Palette of a palette file, or of any image we can open.
+pub fn load_palette_from<P: AsRef<Path>>(filename: P) -> Result<Palette<u32>, Error> {
+    if is_palette(&filename) {
+        load_palette(filename)
+    } else {
+        Ok(load_sprite(filename)?.palette)
     }
-    let nq: &NeuQuant = unsafe {
-        ::std::mem::transmute(nq)
-    };
-    let pal: Vec<_> = nq.colormap.iter()
-        .map(|c| {
-            ((c.r as u32) << 24) |
-            ((c.g as u32) << 16) |
-            ((c.b as u32) << 8) |
-            ((c.a as u32) << 0)
-        })
-        .collect();
-    pal
 }
 
-pub fn load_sprite<P: AsRef<Path>>(filename: P) -> Option<Receiver> {
-    use image::{load, ImageFormat};
-    use image::imageops::{index_colors, dither};
-    use image::math::nq::NeuQuant;
+pub fn save_palette<P: AsRef<Path>>(filename: P, palette: &Palette<u32>) -> io::Result<()> {
     use std::fs::File;
-    use std::io::BufReader;
-
-    let format = {
-        let ext = filename.as_ref().extension()
-            .and_then(|s| s.to_str())
-            .map_or("".to_string(), |s| s.to_ascii_lowercase());
-        match &*ext {
-            "gif" => ImageFormat::GIF,
-            "png" => ImageFormat::PNG,
-            "jpeg" | "jpg" => ImageFormat::JPEG,
-            _ => unimplemented!(),
-        }
+    use std::io::BufWriter;
+
+    let kind = palette_kind(&filename)?;
+    let mut w = BufWriter::new(File::create(filename)?);
+    format::palette::write(&mut w, palette, kind)
+}
+
+pub fn load_sprite<P: AsRef<Path>>(filename: P) -> Result<Receiver, Error> {
+    use image::ImageFormat;
+    use std::fs::File;
+    use std::io::Read;
+
+    let ext = extension(&filename);
+    match &*ext {
+        native::EXTENSION | "ase" | "aseprite" | "gif" | "png" | "jpeg" | "jpg" |
+        "bmp" | "pcx" | "iff" | "ilbm" | "lbm" | "bbm" => (),
+        "" => return Err(Error::Unsupported("file without extension".to_string())),
+        ext => return Err(Error::Unsupported(format!(".{} files", ext))),
+    }
+
+    let mut data = Vec::new();
+    File::open(&filename)?.read_to_end(&mut data)?;
+
+    let mut sprite = match &*ext {
+        native::EXTENSION => return Ok(native::read(&mut &data[..])?),
+        "ase" | "aseprite" => format::aseprite::read(&mut &data[..])?,
+        "gif" => format::gif::read(&data[..])?,
+        "bmp" => format::bmp::read(&mut &data[..])?,
+        "pcx" => format::pcx::read(&mut &data[..])?,
+        "iff" | "ilbm" | "lbm" | "bbm" => format::ilbm::read(&mut &data[..])?,
+        "png" => match format::png::read(&data[..])? {
+            Some(sprite) => sprite,
+            None => quantize(&data, ImageFormat::PNG)?,
+        },
+        _ => quantize(&data, ImageFormat::JPEG)?,
     };
 
-    let name = filename
-        .as_ref().file_name().unwrap()
-        .to_str().unwrap()
-        .to_string();
+    sprite.name = filename.as_ref().file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    Ok(sprite)
+}
 
-    let reader = File::open(filename).unwrap();
-    let reader = BufReader::new(reader);
+/// Colours of every pixel for palette extraction, 0 where transparent.
+/// PNG and JPEG are decoded in true colour, other images through their
+/// palette with every frame composited.
+pub fn load_colors<P: AsRef<Path>>(filename: P) -> Result<Vec<u32>, Error> {
+    use image::open;
 
-    let m = load(reader, format).unwrap();
+    match &*extension(&filename) {
+        "png" | "jpeg" | "jpg" => {
+            let m = open(filename).map_err(|err| Error::Decode(err.to_string()))?;
+            Ok(m.to_rgba().pixels()
+                .map(|c| format::from_rgba(c.data[0], c.data[1], c.data[2], c.data[3]))
+                .collect())
+        }
+        _ => {
+            let sprite = load_sprite(filename)?;
+            let mut colors = Vec::new();
+            for frame in 0..sprite.frame_count() {
+                let page = sprite.composite(frame);
+                colors.extend(page.page.iter().map(|&c| {
+                    if Some(c) == page.transparent { 0 } else { sprite.palette[c] }
+                }));
+            }
+            Ok(colors)
+        }
+    }
+}
+
+/// Reduces a true-colour image to 256 colours.
+fn quantize(data: &[u8], format: ::image::ImageFormat) -> Result<Receiver, Error> {
+    use image::load_from_memory_with_format;
+    use image::imageops::{index_colors, dither};
+    use image::math::nq::NeuQuant;
+
+    let m = load_from_memory_with_format(data, format)
+        .map_err(|err| Error::Decode(err.to_string()))?;
     let mut m = m.to_rgba();
 
     let (w, h) = (m.width() as usize, m.height() as usize);
 
-    let mut sprite = Receiver::new(&name, w, h);
+    let mut sprite = Receiver::new("", w, h);
 
     let data: Vec<u8> = m.pixels()
         .flat_map(|c| c.data.iter().map(|&u| u))
@@ -82,29 +224,25 @@ pub fn load_sprite<P: AsRef<Path>>(filename: P) -> Option<Receiver> {
     let map = NeuQuant::new(10, 256, &data);
 
     dither(&mut m, &map);
-    let m = index_colors(&m, &map);
+    let indices = index_colors(&m, &map);
 
+    // after dithering every pixel holds exactly its palette colour
     let mut page = Frame::new(w, h);
-    for (i, p) in m.pixels().enumerate() {
-        page.page[i] = p.data[0];
+    for (i, (p, c)) in indices.pixels().zip(m.pixels()).enumerate() {
+        let idx = p.data[0];
+        let c = c.data;
+        page.page[i] = idx;
+        sprite.palette[idx] = format::from_rgba(c[0], c[1], c[2], c[3]);
     }
 
     sprite.add_layer_page("load", page);
-
-    let pal = get_pal(&map);
-    for (i, &c) in pal.iter().enumerate() {
-        if i < 256 {
-            sprite.palette[i as u8] = c;
-        }
-    }
-
-    Some(sprite)
+    Ok(sprite)
 }
 
 pub fn open_file() -> Option<String> {
     use nfd::{self, Response};
 
-    let result = nfd::dialog().filter("gif,png,jpg,jpeg").open().unwrap();
+    let result = nfd::dialog().filter("aspr,ase,aseprite,gif,png,bmp,pcx,iff,ilbm,lbm,bbm,jpg,jpeg").open().unwrap();
 
     let result = match result {
         Response::Okay(file) => Some(file),
@@ -144,3 +282,64 @@ pub fn open_file() -> Option<String> {
 
     result
 }
+
+fn save_dialog(filter: &str, default_ext: &str) -> Option<String> {
+    use nfd::{self, Response};
+
+    let result = nfd::dialog_save().filter(filter).open().unwrap();
+
+    let result = match result {
+        Response::Okay(file) => Some(file),
+        Response::OkayMultiple(files) => Some(files[0].clone()),
+        Response::Cancel => None,
+    };
+
+    result.map(|file| if extension(&file).is_empty() {
+        format!("{}.{}", file, default_ext)
+    } else {
+        file
+    })
+}
+
+pub fn save_file() -> Option<String> {
+    save_dialog(native::EXTENSION, native::EXTENSION)
+        .map(|file| if is_native(&file) {
+            file
+        } else {
+            format!("{}.{}", file, native::EXTENSION)
+        })
+}
+
+pub fn open_palette_file() -> Option<String> {
+    use nfd::{self, Response};
+
+    match nfd::dialog().filter(format::palette::EXTENSIONS).open().unwrap() {
+        Response::Okay(file) => Some(file),
+        Response::OkayMultiple(files) => Some(files[0].clone()),
+        Response::Cancel => None,
+    }
+}
+
+/// Palette files first, then images to take the palette of.
+pub fn open_palette_source() -> Option<String> {
+    use nfd::{self, Response};
+
+    let filter = format!("{};aspr,ase,aseprite,gif,png,bmp,pcx,iff,ilbm,lbm,bbm", format::palette::EXTENSIONS);
+    match nfd::dialog().filter(&filter).open().unwrap() {
+        Response::Okay(file) => Some(file),
+        Response::OkayMultiple(files) => Some(files[0].clone()),
+        Response::Cancel => None,
+    }
+}
+
+pub fn save_palette_file() -> Option<String> {
+    save_dialog(format::palette::EXTENSIONS, "gpl")
+}
+
+pub fn export_png_file() -> Option<String> {
+    save_dialog("png", "png")
+}
+
+pub fn export_file() -> Option<String> {
+    save_dialog("png,gif,aseprite,ase,bmp,pcx,iff,lbm", "png")
+}
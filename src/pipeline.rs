@@ -0,0 +1,304 @@
+// Asset build driven by a TOML manifest, usually `res/res.toml`:
+//
+//     [palettes]
+//     main = "palettes/main.gpl"
+//
+//     [[sprite]]
+//     source = "images/rabbitv3.png"
+//     palette = "main"        # name from [palettes] or a path
+//     remap = true
+//     scale = 2
+//
+//     [[sprite.target]]
+//     output = "build/rabbitv3.png"
+//     sheet = "grid=4"        # row, column, grid or grid=COLUMNS
+//     padding = 1
+//
+//     [[sprite.target]]
+//     output = "build/rabbitv3.gif"
+//     loop = 3                # GIF plays, 0 loops forever
+//
+// Paths are relative to the manifest. Plain `images = [...]` entries are
+// copied to `build/` as paletted PNGs. A target is rebuilt only when one of
+// its outputs, sheet metadata included, is missing or older than the
+// manifest, its source or its palette.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use toml;
+
+use cli::Args;
+use format::{self, Source};
+use format::gif::Loop;
+
+pub const DEFAULT_MANIFEST: &str = "res/res.toml";
+/// Where `images` entries end up, relative to the manifest.
+pub const IMAGES_OUTPUT: &str = "build";
+
+#[derive(Deserialize, Default)]
+pub struct Manifest {
+    /// Images built with default settings into `IMAGES_OUTPUT`.
+    #[serde(default)]
+    pub images: Vec<String>,
+    #[serde(default)]
+    pub palettes: BTreeMap<String, String>,
+    #[serde(default, rename = "sprite")]
+    pub sprites: Vec<Sprite>,
+}
+
+#[derive(Deserialize)]
+pub struct Sprite {
+    pub source: String,
+    pub palette: Option<String>,
+    #[serde(default)]
+    pub remap: bool,
+    pub scale: Option<usize>,
+    #[serde(default, rename = "target")]
+    pub targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+pub struct Target {
+    pub output: String,
+    /// Overrides the sprite scale.
+    pub scale: Option<usize>,
+    pub layer: Option<usize>,
+    pub sheet: Option<String>,
+    #[serde(default)]
+    pub padding: usize,
+    #[serde(default)]
+    pub extrude: usize,
+    /// GIF plays, 0 loops forever.
+    #[serde(default, rename = "loop")]
+    pub plays: u16,
+}
+
+pub fn parse(src: &str) -> io::Result<Manifest> {
+    toml::from_str(src).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+pub fn load<P: AsRef<Path>>(filename: P) -> io::Result<Manifest> {
+    parse(&fs::read_to_string(filename)?)
+}
+
+/// A single output with everything it depends on.
+pub struct Job {
+    pub args: Args,
+    pub inputs: Vec<PathBuf>,
+}
+
+impl Manifest {
+    /// Expands every target into a job with paths joined onto `root`.
+    pub fn jobs(&self, root: &Path, manifest: &Path) -> Result<Vec<Job>, String> {
+        let path = |s: &str| root.join(s).to_string_lossy().into_owned();
+
+        let mut jobs = Vec::new();
+        for image in &self.images {
+            let name = Path::new(image).file_name()
+                .ok_or_else(|| format!("bad image path: {}", image))?;
+            let output = Path::new(IMAGES_OUTPUT).join(name);
+            jobs.push(Job {
+                args: Args {
+                    input: path(image),
+                    output: path(&output.to_string_lossy()),
+                    palette: None,
+                    remap: false,
+                    scale: 1,
+                    source: Source::Composite,
+                    sheet: None,
+                    repeat: Loop::Forever,
+                },
+                inputs: vec![manifest.to_path_buf(), PathBuf::from(path(image))],
+            });
+        }
+
+        for sprite in &self.sprites {
+            let palette = sprite.palette.as_ref()
+                .map(|p| path(self.palettes.get(p).unwrap_or(p)));
+
+            for target in &sprite.targets {
+                let source = target.layer.map_or(Source::Composite, Source::Layer);
+                let sheet = match target.sheet {
+                    Some(ref layout) => Some(format::sheet::Options {
+                        source,
+                        layout: ::cli::parse_layout(layout)?,
+                        padding: target.padding,
+                        extrude: target.extrude,
+                    }),
+                    None => None,
+                };
+
+                let mut inputs = vec![manifest.to_path_buf(), PathBuf::from(path(&sprite.source))];
+                inputs.extend(palette.iter().map(PathBuf::from));
+
+                jobs.push(Job {
+                    args: Args {
+                        input: path(&sprite.source),
+                        output: path(&target.output),
+                        palette: palette.clone(),
+                        remap: sprite.remap,
+                        scale: target.scale.or(sprite.scale).unwrap_or(1).max(1),
+                        source,
+                        sheet,
+                        repeat: Loop::plays(target.plays),
+                    },
+                    inputs,
+                });
+            }
+        }
+        Ok(jobs)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Job {
+    /// Every file the job writes: sheets get `.json` and `.ron` next to the image.
+    pub fn outputs(&self) -> Vec<PathBuf> {
+        let output = PathBuf::from(&self.args.output);
+        if self.args.sheet.is_some() {
+            vec![output.with_extension("json"), output.with_extension("ron"), output]
+        } else {
+            vec![output]
+        }
+    }
+
+    pub fn is_outdated(&self) -> bool {
+        // the oldest output decides, a missing one is always rebuilt
+        let mut built: Option<SystemTime> = None;
+        for output in self.outputs() {
+            match modified(&output) {
+                Some(time) => built = Some(built.map_or(time, |built| built.min(time))),
+                None => return true,
+            }
+        }
+        let built = match built {
+            Some(time) => time,
+            None => return true,
+        };
+        self.inputs.iter().any(|input| modified(input).map_or(true, |time| time > built))
+    }
+
+    pub fn run(&self) -> Result<(), format::Error> {
+        if let Some(dir) = Path::new(&self.args.output).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        ::cli::process(&self.args)
+    }
+}
+
+/// `asprite build [MANIFEST] [--force]`, returns the exit code.
+pub fn run(args: &[String]) -> i32 {
+    let force = args.iter().any(|arg| arg == "--force");
+    let manifest = args.iter()
+        .find(|arg| !arg.starts_with('-'))
+        .map_or(DEFAULT_MANIFEST, |s| s.as_str());
+    let manifest = Path::new(manifest);
+    let root = manifest.parent().unwrap_or(Path::new(""));
+
+    let jobs = load(manifest)
+        .map_err(|err| err.to_string())
+        .and_then(|m| m.jobs(root, manifest));
+    let jobs = match jobs {
+        Ok(jobs) => jobs,
+        Err(err) => {
+            eprintln!("error: {}: {}", manifest.display(), err);
+            return 2;
+        }
+    };
+
+    let (mut built, mut failed) = (0, 0);
+    for job in jobs.iter().filter(|job| force || job.is_outdated()) {
+        println!("build: {}", job.args.output);
+        match job.run() {
+            Ok(()) => built += 1,
+            Err(err) => {
+                eprintln!("error: {}: {}", job.args.output, err);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{} built, {} up to date, {} failed", built, jobs.len() - built - failed, failed);
+    if failed == 0 { 0 } else { 1 }
+}
+
+#[test]
+fn manifest_jobs() {
+    let m = parse(r#"
+        images = ["images/a.png"]
+
+        [palettes]
+        main = "pal/main.gpl"
+
+        [[sprite]]
+        source = "images/a.png"
+        palette = "main"
+        scale = 2
+
+        [[sprite.target]]
+        output = "build/a.gif"
+        loop = 1
+
+        [[sprite.target]]
+        output = "build/a_sheet.png"
+        sheet = "row"
+        layer = 1
+        scale = 1
+    "#).unwrap();
+
+    let root = Path::new("res");
+    let jobs = m.jobs(root, &root.join("res.toml")).unwrap();
+    assert_eq!(jobs.len(), 3);
+
+    let image = &jobs[0].args;
+    assert_eq!(Path::new(&image.output), root.join("build/a.png"));
+    assert!(image.palette.is_none() && image.sheet.is_none());
+
+    let a = &jobs[1].args;
+    assert_eq!(Path::new(&a.input), root.join("images/a.png"));
+    assert_eq!(a.palette.as_ref().map(Path::new), Some(root.join("pal/main.gpl").as_path()));
+    assert_eq!(a.scale, 2);
+    assert!(a.sheet.is_none());
+    assert_eq!(a.repeat, Loop::Once);
+    assert_eq!(jobs[1].inputs.len(), 3);
+
+    let b = &jobs[2].args;
+    assert_eq!(b.scale, 1);
+    assert_eq!(b.repeat, Loop::Forever);
+    assert_eq!(b.sheet.unwrap().source, Source::Layer(1));
+
+    assert_eq!(jobs[2].outputs().len(), 3);
+
+    // missing outputs are always rebuilt
+    assert!(jobs[0].is_outdated());
+}
+
+#[test]
+fn sheet_metadata_outdated() {
+    use std::env;
+
+    let dir = env::temp_dir().join(format!("asprite-build-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let manifest = dir.join("res.toml");
+    fs::write(&manifest, "").unwrap();
+    fs::write(dir.join("a.png"), "").unwrap();
+
+    let m = parse("[[sprite]]\nsource = \"a.png\"\n[[sprite.target]]\noutput = \"a_sheet.png\"\nsheet = \"row\"\n").unwrap();
+    let jobs = m.jobs(&dir, &manifest).unwrap();
+    fs::write(dir.join("a_sheet.png"), "").unwrap();
+    fs::write(dir.join("a_sheet.ron"), "").unwrap();
+    assert!(jobs[0].is_outdated(), "the .json is missing");
+
+    fs::write(dir.join("a_sheet.json"), "").unwrap();
+    assert!(!jobs[0].is_outdated());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,4 +1,4 @@
-use math::Rect;
+use math::{Rect, Point2};
 use draw::{Bounded, CanvasRead, CanvasWrite};
 use tool::{Editor, Brush, PreviewContext};
 
@@ -13,6 +13,38 @@ impl<'a> Bounded<i32> for Prev<'a> {
     fn bounds(&self) -> Rect<i32> { self.rect }
 }
 
+impl<'a> Prev<'a> {
+    /// Outlines the brush at `p` in colours that stand out against the pixels under the line.
+    pub fn brush_outline(&mut self, p: Point2<i32>) {
+        let editor = self.editor;
+        let (brush, r) = editor.brush();
+        let r = r.shift_x_y(p.x, p.y);
+        let (w, h) = (r.dx(), r.dy());
+        let inside = |x: i32, y: i32| x >= 0 && y >= 0 && x < w && y < h && brush[(x + y * w) as usize];
+
+        for y in -1..h + 1 {
+            for x in -1..w + 1 {
+                let edge = !inside(x, y) &&
+                    (inside(x - 1, y) || inside(x + 1, y) || inside(x, y - 1) || inside(x, y + 1));
+                let (x, y) = (r.min.x + x, r.min.y + y);
+                if edge && self.rect.contains_xy(x, y) {
+                    let c = editor.overlay(x, y);
+                    unsafe { self.put(x, y, c.to_le()) }
+                }
+            }
+        }
+    }
+
+    unsafe fn put(&mut self, x: i32, y: i32, c: u32) {
+        let m = self.editor.image.as_receiver();
+        let ptr = self.ptr.add((m.width * y as usize + x as usize) * 4);
+        *ptr.add(0) = ( c        & 0xFF) as u8;
+        *ptr.add(1) = ((c >>  8) & 0xFF) as u8;
+        *ptr.add(2) = ((c >> 16) & 0xFF) as u8;
+        *ptr.add(3) = ((c >> 24) & 0xFF) as u8;
+    }
+}
+
 impl<'a> CanvasWrite<u8, i32> for Prev<'a> {
     #[inline(always)]
     unsafe fn set_unchecked(&mut self, x: i32, y: i32, color: u8) {
@@ -22,11 +54,7 @@ impl<'a> CanvasWrite<u8, i32> for Prev<'a> {
         } else {
             m.palette[color].to_le()
         };
-        let ptr = self.ptr.add((m.width * y as usize + x as usize) * 4);
-        *ptr.add(0) = ( c        & 0xFF) as u8;
-        *ptr.add(1) = ((c >>  8) & 0xFF) as u8;
-        *ptr.add(2) = ((c >> 16) & 0xFF) as u8;
-        *ptr.add(3) = ((c >> 24) & 0xFF) as u8;
+        self.put(x, y, c);
     }
 }
 
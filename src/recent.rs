@@ -0,0 +1,115 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub const MAX_RECENT: usize = 10;
+
+/// Per-user directory for asprite settings.
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("asprite"))
+}
+
+/// Most recently used documents, newest first, one path per line on disk.
+pub struct Recent {
+    files: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl Recent {
+    pub fn load() -> Self {
+        Self::load_from(config_dir().map(|dir| dir.join("recent")))
+    }
+
+    pub fn load_from(path: Option<PathBuf>) -> Self {
+        let files = path.as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|s| s.lines()
+                .filter(|line| !line.is_empty())
+                .map(|line| line.to_string())
+                .collect())
+            .unwrap_or_default();
+
+        let mut recent = Self { files, path };
+        recent.retain_existing();
+        recent
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// Drops files that were moved or deleted since.
+    pub fn retain_existing(&mut self) {
+        let len = self.files.len();
+        self.files.retain(|file| Path::new(file).exists());
+        if self.files.len() != len {
+            self.store();
+        }
+    }
+
+    pub fn add(&mut self, file: &str) {
+        let file = fs::canonicalize(file)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| file.to_string());
+
+        self.files.retain(|f| *f != file);
+        self.files.insert(0, file);
+        self.files.truncate(MAX_RECENT);
+        self.store();
+    }
+
+    pub fn remove(&mut self, file: &str) {
+        self.files.retain(|f| f != file);
+        self.store();
+    }
+
+    fn store(&self) {
+        if let Err(err) = self.save() {
+            println!("error: can't save recent files: {}", err);
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut w = fs::File::create(path)?;
+        for file in &self.files {
+            writeln!(w, "{}", file)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn most_recent_first() {
+    let dir = env::temp_dir().join(format!("asprite-recent-{}", ::std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let a = dir.join("a.aspr");
+    let b = dir.join("b.aspr");
+    fs::write(&a, b"").unwrap();
+    fs::write(&b, b"").unwrap();
+
+    let store = dir.join("recent");
+    let mut recent = Recent::load_from(Some(store.clone()));
+    recent.add(a.to_str().unwrap());
+    recent.add(b.to_str().unwrap());
+    recent.add(a.to_str().unwrap());
+    assert_eq!(recent.files().len(), 2);
+    assert!(recent.files()[0].ends_with("a.aspr"));
+
+    fs::remove_file(&b).unwrap();
+    let recent = Recent::load_from(Some(store));
+    assert_eq!(recent.files().len(), 1);
+    assert!(recent.files()[0].ends_with("a.aspr"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -202,6 +202,10 @@ impl<'a> System<'a> for Canvas {
             let poll = self.events.poll_iter();
 
             for event in poll {
+                // drops come from another window, key events never told us about modifiers
+                if let Event::DropFile { .. } = event {
+                    app.keymod = self.sdl.keyboard().mod_state();
+                }
                 app.event(event.clone());
                 if app.quit {
                     *quit = true;
@@ -4,6 +4,7 @@
 use ui::*;
 use render::Canvas;
 use draw::Shape;
+use format::palette::SortBy;
 
 pub const TRANSPARENT: u32 = 0x000000_00;
 
@@ -16,7 +17,12 @@ pub static PAL_GAMEBOY: &[u32] = &[
 ];
 
 pub const GRID_COLOR: u32 = 0xFF0000_AA;
-pub const CORNER_COLOR: u32 = 0x00FF00_AA;
+
+/// Tile property toggled in the grid panel.
+pub const COLLISION: &str = "collision";
+
+pub const CHECKER_LIGHT: u32 = 0x333333_FF;
+pub const CHECKER_DARK: u32 = 0x000000_FF;
 
 
 pub const ICON_TOOL_FREEHAND: usize = 1000_0;
@@ -61,6 +67,8 @@ pub const BAR_BG: u32 = rgba(0x3F4957_FF);
 pub const BAR_TITLE_BG: u32 = rgba(0x525b68_FF);
 pub const BAR_TITLE_HEIGHT: f32 = 20.0;
 
+pub const ERROR_BG: u32 = rgba(0xC44448_EE);
+
 pub const BTN_NORMAL: u32 =  rgba(0x4E5763_FF);
 pub const BTN_HOVERED: u32 = rgba(0x3E4855_FF);
 pub const BTN_PRESSED: u32 = rgba(0x0076FF_FF);
@@ -125,8 +133,10 @@ pub const MENUBAR: MenuBar<Canvas> = MenuBar {
 
 #[derive(Clone, Debug)]
 pub enum Command {
-    New, Open, Recent,
+    New, Open, Recent(usize), ImportSheet,
     Save, SaveAs,
+    Export, ExportLayer, ExportSheet, ExportScaled, ExportTileset,
+    ImportPalette, RemapPalette, ExportPalette, ExtractPalette, CompactPalette, SortPalette(SortBy), ConvertPalette,
     Quit,
 }
 
@@ -152,16 +162,33 @@ const MENU_STYLE: MenuStyle<Canvas> = MenuStyle {
     sep_inset: 2.0,
 };
 
-pub const FILE_ITEMS: [Item<Command>; 8] = [
+pub fn file_items<'a>(recent: &'a [Item<'a, Command>]) -> [Item<'a, Command>; 25] {[
     Item::Text(Command::New, "New", "Ctrl-N"),
     Item::Text(Command::Open, "Open", "Ctrl-O"),
-    Item::Text(Command::Recent, "Recent", ">"),
+    Item::Menu("Recent", recent),
+    Item::Text(Command::ImportSheet, "Import sheet...", ""),
     Item::Separator,
     Item::Text(Command::Save, "Save", "Ctrl-S"),
     Item::Text(Command::SaveAs, "Save as...", "Shift-Ctrl-S"),
     Item::Separator,
+    Item::Text(Command::Export, "Export...", "Ctrl-E"),
+    Item::Text(Command::ExportLayer, "Export layer...", ""),
+    Item::Text(Command::ExportSheet, "Export sheet...", ""),
+    Item::Text(Command::ExportScaled, "Export scaled...", ""),
+    Item::Text(Command::ExportTileset, "Export tileset...", ""),
+    Item::Separator,
+    Item::Text(Command::ImportPalette, "Import palette...", ""),
+    Item::Text(Command::RemapPalette, "Import palette and remap...", ""),
+    Item::Text(Command::ExportPalette, "Export palette...", ""),
+    Item::Text(Command::ExtractPalette, "Extract palette...", ""),
+    Item::Text(Command::CompactPalette, "Compact palette", ""),
+    Item::Text(Command::ConvertPalette, "Convert to palette...", ""),
+    Item::Text(Command::SortPalette(SortBy::Hue), "Sort palette by hue", ""),
+    Item::Text(Command::SortPalette(SortBy::Luminance), "Sort palette by luminance", ""),
+    Item::Text(Command::SortPalette(SortBy::Usage), "Sort palette by usage", ""),
+    Item::Separator,
     Item::Text(Command::Quit, "Quit", "Ctrl-Q"),
-];
+]}
 
 pub const BRUSH_ITEMS: [Item<Shape>; 13] = [
     Item::Text(Shape::Round, "Round", ""),
@@ -11,26 +11,86 @@ use draw::{
     Frame,
     Palette,
     Shape,
+    XOR,
 };
 
 use super::{
     Brush,
+    Layer,
+    Properties,
     Receiver,
 };
 
+/// A change to the image, applying and undoing both swap the stored state in.
 #[derive(Debug)]
-pub struct DrawCommand {
-    page: Frame,
-    frame: usize,
-    layer: usize,
+pub enum DrawCommand {
+    Page {
+        page: Frame,
+        frame: usize,
+        layer: usize,
+    },
+    Color {
+        index: u8,
+        color: u32,
+    },
+    /// Palette and the pages of every layer, for changes that remap the whole sprite.
+    Sprite {
+        palette: Palette<u32>,
+        layers: Vec<Vec<Frame>>,
+    },
+    /// A new top layer while it's not in the image, taken back on undo.
+    Layer(Option<Layer>),
+    /// Properties of a tile, `None` when it has none.
+    Tile {
+        id: usize,
+        props: Option<Properties>,
+    },
 }
 
 impl DrawCommand {
     pub fn new(frame: usize, layer: usize, page: Frame) -> Self {
-        Self { frame, layer, page }
+        DrawCommand::Page { frame, layer, page }
+    }
+    pub fn color(index: u8, color: u32) -> Self {
+        DrawCommand::Color { index, color }
+    }
+    pub fn sprite(image: &Receiver) -> Self {
+        DrawCommand::Sprite {
+            palette: Palette {
+                map: image.palette.map,
+                size: image.palette.size,
+                transparent: image.palette.transparent,
+            },
+            layers: image.data.iter().map(|layer| layer.frames.clone()).collect(),
+        }
     }
     fn run(&mut self, image: &mut Receiver) -> Result<(), ()> {
-        swap(&mut self.page, image.page_mut(self.frame, self.layer));
+        match *self {
+            DrawCommand::Page { ref mut page, frame, layer } =>
+                swap(page, image.page_mut(frame, layer)),
+            DrawCommand::Color { index, ref mut color } =>
+                swap(color, &mut image.palette[index]),
+            DrawCommand::Sprite { ref mut palette, ref mut layers } => {
+                swap(palette, &mut image.palette);
+                for (frames, layer) in layers.iter_mut().zip(&mut image.data) {
+                    swap(frames, &mut layer.frames);
+                }
+            }
+            DrawCommand::Layer(ref mut layer) => match layer.take() {
+                Some(layer) => image.data.push(layer),
+                None => {
+                    *layer = image.data.pop();
+                    image.layer = image.layer.min(image.data.len().saturating_sub(1));
+                }
+            },
+            DrawCommand::Tile { id, ref mut props } => {
+                let old = image.tiles.remove(&id);
+                if let Some(props) = props.take() {
+                    image.tiles.insert(id, props);
+                }
+                *props = old;
+            }
+        }
         Ok(())
     }
 }
@@ -51,6 +111,16 @@ pub struct Editor {
     pub color: u8,
 
     canvas: Frame,
+    changes: usize,
+    /// Palette entry being edited and its colour before the edit.
+    pal_edit: Option<(u8, u32)>,
+
+    /// Contrast pairs for overlays, the palette they were computed for
+    /// and its lightest entry, shown over transparent pixels.
+    xor: XOR,
+    xor_map: [u32; 256],
+    xor_size: usize,
+    light: u8,
 }
 
 impl Editor {
@@ -65,6 +135,13 @@ impl Editor {
             brush_size_old: brush_size,
             brush_offset: Point2::new(-5, -5),
             color: 1,
+            changes: 0,
+            pal_edit: None,
+            xor: XOR::new(),
+            // never a palette colour, so the first update computes the table
+            xor_map: [!0; 256],
+            xor_size: 0,
+            light: 0,
         }
     }
 
@@ -108,6 +185,13 @@ impl Editor {
     pub fn recreate(&mut self, image: Receiver) {
         self.canvas = image.current().clone();
         self.image = Record::new(image);
+        self.changes = 0;
+        self.pal_edit = None;
+    }
+
+    /// Counts commits, undos and redos since the image was created or replaced.
+    pub fn changes(&self) -> usize {
+        self.changes
     }
 
     pub fn zoom(&self) -> i32 {
@@ -142,15 +226,152 @@ impl Editor {
         self.image.as_receiver().palette[color]
     }
 
+    /// Shows `color` at `index` right away, the edit goes into the
+    /// history as one step on `commit_pal`.
+    pub fn preview_pal(&mut self, index: u8, color: u32) {
+        if self.pal_edit.map_or(false, |(i, _)| i != index) {
+            self.commit_pal();
+        }
+        let palette = &mut self.image.as_mut_receiver().palette;
+        if self.pal_edit.is_none() {
+            self.pal_edit = Some((index, palette[index]));
+        }
+        palette[index] = color;
+    }
+
+    pub fn commit_pal(&mut self) {
+        use std::mem::replace;
+        if let Some((index, old)) = self.pal_edit.take() {
+            let color = replace(&mut self.image.as_mut_receiver().palette[index], old);
+            if color != old {
+                let _ = self.image.apply(DrawCommand::color(index, color)).unwrap();
+                self.changes += 1;
+            }
+        }
+    }
+
+    pub fn set_pal(&mut self, index: u8, color: u32) {
+        self.preview_pal(index, color);
+        self.commit_pal();
+    }
+
+    /// Runs `f` on the sprite as one step in the history. Meant for palette
+    /// and pixel rewrites, the layer list must stay the same.
+    pub fn rewrite<F: FnOnce(&mut Receiver)>(&mut self, f: F) {
+        use super::Context;
+        self.commit_pal();
+        let mut command = DrawCommand::sprite(self.image.as_receiver());
+        {
+            let m = self.image.as_mut_receiver();
+            f(m);
+            // the command takes the new state and the image gets the old one back,
+            // applying swaps them again
+            let _ = command.run(m);
+        }
+        let _ = self.image.apply(command).unwrap();
+        self.changes += 1;
+        self.sync();
+    }
+
+    /// Puts a layer on top of the image as one undo step.
+    pub fn add_layer(&mut self, layer: Layer) {
+        use super::Context;
+        self.commit_pal();
+        let _ = self.image.apply(DrawCommand::Layer(Some(layer))).unwrap();
+        self.changes += 1;
+        self.sync();
+    }
+
+    /// Replaces the properties of a tile as one undo step.
+    pub fn set_tile(&mut self, id: usize, props: Option<Properties>) {
+        if self.image.as_receiver().tiles.get(&id) == props.as_ref() {
+            return;
+        }
+        self.commit_pal();
+        let _ = self.image.apply(DrawCommand::Tile { id, props }).unwrap();
+        self.changes += 1;
+    }
+
+    /// Recomputes the contrast pairs if the palette changed since last time.
+    /// Waits while a palette edit is previewed, the pairs follow once it's committed.
+    pub fn update_contrast(&mut self) {
+        use draw::color::{to_oklab, luminance};
+
+        let map = self.image.as_receiver().palette.map;
+        let size = self.image.as_receiver().palette.size.max(1).min(256);
+        if self.pal_edit.is_some() || (map[..] == self.xor_map[..] && size == self.xor_size) {
+            return;
+        }
+        self.xor_map = map;
+        self.xor_size = size;
+
+        let lab: Vec<(f32, f32, f32)> = map.iter().map(|&c| to_oklab(c)).collect();
+        self.xor.compute(size, |a, b| {
+            let (a, b) = (lab[a as usize], lab[b as usize]);
+            let (l, x, y) = (a.0 - b.0, a.1 - b.1, a.2 - b.2);
+            ((l * l + x * x + y * y) * 1_000_000.0) as isize
+        });
+
+        self.light = (0..size)
+            .max_by(|&a, &b| luminance(map[a]).partial_cmp(&luminance(map[b])).unwrap())
+            .unwrap_or(0) as u8;
+    }
+
+    /// Index shown at the pixel: the topmost visible layer that isn't transparent there.
+    pub fn top_index(&self, x: usize, y: usize) -> Option<u8> {
+        let image = self.image.as_receiver();
+        if x >= image.width || y >= image.height {
+            return None;
+        }
+        image.data.iter().enumerate().rev()
+            .filter(|&(_, layer)| layer.visible)
+            .filter_map(|(id, layer)| if id == image.layer {
+                Some(&self.canvas)
+            } else {
+                layer.frames.get(image.frame)
+            })
+            .map(|page| (page.page[x + y * image.width], page.transparent))
+            .find(|&(c, t)| Some(c) != t)
+            .map(|(c, _)| c)
+    }
+
+    /// Colour standing out against the pixel, for grid lines and cursors.
+    /// Points outside the image take the nearest edge pixel.
+    pub fn overlay(&self, x: i32, y: i32) -> u32 {
+        let size = self.size();
+        let x = x.max(0).min(size.x - 1) as usize;
+        let y = y.max(0).min(size.y - 1) as usize;
+        let index = self.top_index(x, y).map_or(self.light, |c| self.xor[c]);
+        self.image.as_receiver().palette[index] | 0xFF
+    }
+
+    pub fn frame(&self) -> usize {
+        self.image.as_receiver().frame
+    }
+
+    pub fn set_frame(&mut self, frame: usize) {
+        use super::Context;
+        {
+            let m = self.image.as_mut_receiver();
+            let count = m.data[m.layer].frames.len();
+            m.frame = frame.min(count - 1);
+        }
+        self.sync();
+    }
+
     pub fn redo(&mut self) {
         use super::Context;
+        self.commit_pal();
         self.image.redo();
+        self.changes += 1;
         self.sync();
     }
 
     pub fn undo(&mut self) {
         use super::Context;
+        self.commit_pal();
         self.image.undo();
+        self.changes += 1;
         self.sync();
     }
 
@@ -162,16 +383,13 @@ impl Editor {
             if !layer.visible {
                 continue;
             }
-            for (frame_id, _) in layer.frames.iter().enumerate() {
-                let is_canvas = layer_id == current_layer && frame_id == current_frame;
-                let page = if is_canvas {
-                    Some(&self.canvas)
-                } else {
-                    Some(image.page(layer_id, frame_id))
-                };
-                if let Some(page) = page {
-                    f(&page, &image.palette)
-                }
+            let page = if layer_id == current_layer {
+                Some(&self.canvas)
+            } else {
+                layer.frames.get(current_frame)
+            };
+            if let Some(page) = page {
+                f(&page, &image.palette)
             }
         }
     }
@@ -216,6 +434,7 @@ impl super::Context<i32, u8> for Editor {
         let frame = self.image.as_receiver().frame;
         let _ = self.image
             .apply(DrawCommand::new(layer, frame, page.clone())).unwrap();
+        self.changes += 1;
         self.sync();
     }
 
@@ -11,7 +11,7 @@ mod primitive;
 mod bucket;
 mod eye_dropper;
 
-pub use self::receiver::{Receiver, Layer};
+pub use self::receiver::{Receiver, Layer, Cycle, Property, Properties};
 pub use self::editor::Editor;
 
 pub use self::freehand::Freehand;
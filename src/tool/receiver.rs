@@ -1,11 +1,41 @@
+use std::collections::BTreeMap;
+
 use math::{Rect, Vector2, Point2};
 use draw::{Frame, Palette, Bounded};
 
+pub const DEFAULT_DURATION: u32 = 100;
+
+/// Colour cycling range, as in Deluxe Paint's `CRNG` chunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cycle {
+    pub low: u8,
+    pub high: u8,
+    /// 16384 means 60 steps per second.
+    pub rate: u16,
+    pub active: bool,
+    pub reverse: bool,
+}
+
+/// Per-tile value, the types Tiled knows.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Property {
+    Bool(bool),
+    Int(i32),
+    Str(String),
+}
+
+pub type Properties = BTreeMap<String, Property>;
+
 pub struct Receiver {
     pub data: Vec<Layer>,
+    /// Frame durations in milliseconds.
+    pub durations: Vec<u32>,
 
     pub name: String,
     pub palette: Palette<u32>,
+    pub cycles: Vec<Cycle>,
+    /// Properties of grid cells by tile id, counted row by row.
+    pub tiles: BTreeMap<usize, Properties>,
     pub width: usize,
     pub height: usize,
 
@@ -31,7 +61,10 @@ impl Receiver {
         Self {
             name: name.to_string(),
             data: Vec::new(),
+            durations: Vec::new(),
             palette: Palette::new(0, None),
+            cycles: Vec::new(),
+            tiles: BTreeMap::new(),
             width,
             height,
             frame: 0,
@@ -81,6 +114,47 @@ impl Receiver {
         self.data[layer].get_mut(frame)
     }
 
+    pub fn frame_count(&self) -> usize {
+        self.data.iter().map(|layer| layer.frames.len()).max().unwrap_or(0)
+    }
+
+    pub fn duration(&self, frame: usize) -> u32 {
+        self.durations.get(frame).cloned().unwrap_or(DEFAULT_DURATION)
+    }
+
+    pub fn set_duration(&mut self, frame: usize, duration: u32) {
+        if self.durations.len() <= frame {
+            self.durations.resize(frame + 1, DEFAULT_DURATION);
+        }
+        self.durations[frame] = duration;
+    }
+
+    /// Blends all visible layers of the frame into a single page.
+    pub fn composite(&self, frame: usize) -> Frame {
+        let mut dst = Frame::new(self.width, self.height);
+        let mut pages = self.data.iter()
+            .filter(|layer| layer.visible)
+            .filter_map(|layer| layer.frames.get(frame))
+            .peekable();
+
+        if let Some(first) = pages.peek() {
+            dst.transparent = first.transparent;
+            let fill = first.transparent.unwrap_or(0);
+            for c in &mut dst.page {
+                *c = fill;
+            }
+        }
+
+        for page in pages {
+            for (dst, &src) in dst.page.iter_mut().zip(&page.page) {
+                if Some(src) != page.transparent {
+                    *dst = src;
+                }
+            }
+        }
+        dst
+    }
+
     pub fn add_layer(&mut self, name: &str) {
         let mut layer = Layer::new(name);
         let page = Frame::new(self.width, self.height);
@@ -95,6 +169,7 @@ impl Receiver {
     }
 }
 
+#[derive(Debug)]
 pub struct Layer {
     pub frames: Vec<Frame>,
     pub name: String,
@@ -6,7 +6,7 @@ use std::marker::PhantomData;
 pub enum Item<'a, T: Clone + 'a> {
     Text(T, &'a str, &'a str),
     Separator,
-    Menu(&'a [Item<'a, T>]),
+    Menu(&'a str, &'a [Item<'a, T>]),
 }
 
 pub struct ItemStyle<D: ?Sized + Graphics> {
@@ -44,21 +44,46 @@ impl<T: Clone, D: ?Sized + Graphics> Menu<D, T> {
         ctx: &Context<'a, D>, state: &mut UiState,
         id: Id, base_rect: Rect<f32>, items: &'b [Item<'c, T>],
     ) -> MenuEvent<T> {
-        let mut min = Point2::new(base_rect.min.x, base_rect.max.y);
+        let min = Point2::new(base_rect.min.x, base_rect.max.y);
 
+        let mut event = None;
+        let any_hovering = self.run_items(ctx, min, items, &mut event);
+
+        if let Some(item) = event {
+            state.active_widget = None;
+            MenuEvent::Clicked(item)
+        } else if !any_hovering && !ctx.is_cursor_in_rect(base_rect) {
+            state.active_widget = None;
+            MenuEvent::Exit
+        } else {
+            state.active_widget = Some(id);
+            MenuEvent::Nothing
+        }
+    }
+
+    fn height(&self, items: &[Item<T>]) -> f32 {
+        items.iter().map(|item| match item {
+            &Item::Separator => self.style.sep_height,
+            _ => self.style.text_height,
+        }).sum()
+    }
+
+    fn run_items<'a, 'c>(&self,
+        ctx: &Context<'a, D>, mut min: Point2<f32>,
+        items: &[Item<'c, T>], event: &mut Option<T>,
+    ) -> bool {
         let mut any_hovering = false;
 
         let label_align = Vector2::new(0.0, 0.5);
         let shortcut_align = Vector2::new(1.0, 0.5);
 
-        let mut event = None;
         for item in items.iter() {
             let rect = match item {
                 &Item::Text(ref id, name, shortcut) => {
                     let rect = Rect { min, max: Point2::new(min.x + self.style.width, min.y + self.style.text_height) };
                     let style = if ctx.is_cursor_in_rect(rect) {
                         if ctx.was_released() {
-                            event = Some(id.clone());
+                            *event = Some(id.clone());
                         }
                         &self.style.hovered
                     } else {
@@ -76,7 +101,25 @@ impl<T: Clone, D: ?Sized + Graphics> Menu<D, T> {
                     ctx.quad(self.style.separator, rect.pad_y(self.style.sep_inset));
                     rect
                 }
-                &Item::Menu(_) => unimplemented!(),
+                &Item::Menu(name, sub) => {
+                    let rect = Rect { min, max: Point2::new(min.x + self.style.width, min.y + self.style.text_height) };
+
+                    // the submenu stays open while the cursor is on its row or inside it
+                    let sub_min = Point2::new(rect.max.x, rect.min.y);
+                    let sub_rect = Rect { min: sub_min, max: Point2::new(sub_min.x + self.style.width, sub_min.y + self.height(sub)) };
+                    let open = ctx.is_cursor_in_rect(rect) || !sub.is_empty() && ctx.is_cursor_in_rect(sub_rect);
+
+                    let style = if open { &self.style.hovered } else { &self.style.normal };
+                    ctx.quad(style.bg, rect);
+                    let inset = rect.pad_x(self.style.text_inset);
+                    ctx.label_rect(inset, label_align, style.label, name);
+                    ctx.label_rect(inset, shortcut_align, style.shortcut, ">");
+
+                    if open {
+                        any_hovering |= self.run_items(ctx, sub_min, sub, event);
+                    }
+                    rect
+                }
             };
 
             min.y += rect.dy();
@@ -84,15 +127,6 @@ impl<T: Clone, D: ?Sized + Graphics> Menu<D, T> {
             any_hovering = any_hovering || ctx.is_cursor_in_rect(rect);
         }
 
-        if let Some(item) = event {
-            state.active_widget = None;
-            MenuEvent::Clicked(item)
-        } else if !any_hovering && !ctx.is_cursor_in_rect(base_rect) {
-            state.active_widget = None;
-            MenuEvent::Exit
-        } else {
-            state.active_widget = Some(id);
-            MenuEvent::Nothing
-        }
+        any_hovering
     }
 }